@@ -12,7 +12,7 @@ use anyhow::{bail, Context};
 use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
-use tabox::configuration::SandboxConfiguration;
+use tabox::configuration::{ClockOffsets, RootFilesystem, SandboxConfiguration, SysfsMount};
 use tabox::syscall_filter::SyscallFilter;
 use tabox::Result;
 use tabox::{Sandbox, SandboxImplementation};
@@ -43,6 +43,21 @@ struct Args {
     #[structopt(long)]
     env: Vec<String>,
 
+    /// Use this directory (read-only) or this tar archive (extracted) as the root of the sandbox
+    #[structopt(long)]
+    rootfs: Option<PathBuf>,
+
+    /// Make a unix socket of the host reachable inside the sandbox
+    ///
+    /// Syntax: --socket=host/path[,sandbox/path], by default the path inside the sandbox is the
+    /// same as the one on the host.
+    #[structopt(long = "socket")]
+    sockets: Vec<String>,
+
+    /// Mount read-only the executable and the shared libraries it needs
+    #[structopt(long)]
+    auto_mount: bool,
+
     /// Mount paths inside the sandbox
     ///
     /// Syntax: --mount=local/path,sandbox/path,rw where only the first argument is required.
@@ -68,6 +83,12 @@ struct Args {
     #[structopt(long)]
     allow_multiprocess: bool,
 
+    /// Use the syscall filter in this JSON file, instead of the default one
+    ///
+    /// Example: {"default_action": "Allow", "rules": [["chmod", {"Errno": 1}], ["fork", "Trap"]]}
+    #[structopt(long)]
+    syscall_filter: Option<PathBuf>,
+
     /// Redirect stdin from this file
     #[structopt(long, short = "i")]
     stdin: Option<PathBuf>,
@@ -96,6 +117,22 @@ struct Args {
     #[structopt(long)]
     wall_limit: Option<u64>,
 
+    /// Run in a new time namespace
+    #[structopt(long)]
+    time_namespace: bool,
+
+    /// Offset in seconds of CLOCK_MONOTONIC in the time namespace (implies --time-namespace)
+    #[structopt(long, allow_hyphen_values = true)]
+    monotonic_offset: Option<i64>,
+
+    /// Offset in seconds of CLOCK_BOOTTIME in the time namespace (implies --time-namespace)
+    #[structopt(long, allow_hyphen_values = true)]
+    boottime_offset: Option<i64>,
+
+    /// Disable ASLR, fix the hostname and run on a single cpu core
+    #[structopt(long)]
+    deterministic: bool,
+
     /// Run on the specified cpu core
     #[structopt(long)]
     cpu_core: Option<usize>,
@@ -111,6 +148,14 @@ struct Args {
     /// Mount /proc
     #[structopt(long)]
     pub mount_proc: bool,
+
+    /// Mount a read-only /sys
+    #[structopt(long)]
+    pub mount_sys: bool,
+
+    /// Show only the CPU topology in /sys (implies --mount-sys)
+    #[structopt(long)]
+    pub sys_cpu_only: bool,
 }
 
 fn main() -> Result<()> {
@@ -133,6 +178,12 @@ fn main() -> Result<()> {
         .gid(args.gid)
         .mount_proc(args.mount_proc);
 
+    if args.sys_cpu_only {
+        config.mount_sys(SysfsMount::CpuTopology);
+    } else if args.mount_sys {
+        config.mount_sys(SysfsMount::Full);
+    }
+
     if let Some(time_limit) = args.time_limit {
         config.time_limit(time_limit);
     }
@@ -161,6 +212,15 @@ fn main() -> Result<()> {
         config.working_directory(working_directory);
     }
 
+    if args.time_namespace || args.monotonic_offset.is_some() || args.boottime_offset.is_some() {
+        config.time_namespace(ClockOffsets {
+            monotonic: args.monotonic_offset.unwrap_or(0),
+            boottime: args.boottime_offset.unwrap_or(0),
+        });
+    }
+
+    config.deterministic(args.deterministic);
+
     if let Some(core) = args.cpu_core {
         config.run_on_core(core);
     }
@@ -186,6 +246,14 @@ fn main() -> Result<()> {
         }
     }
 
+    if let Some(rootfs) = args.rootfs {
+        if rootfs.is_dir() {
+            config.rootfs(RootFilesystem::Directory(rootfs));
+        } else {
+            config.rootfs(RootFilesystem::Tarball(rootfs));
+        }
+    }
+
     for path in args.mount {
         let parts: Vec<&str> = path.split(',').collect();
         let (local, sandbox, writable) = match parts[..] {
@@ -205,10 +273,29 @@ fn main() -> Result<()> {
         config.mount(PathBuf::from(local), PathBuf::from(sandbox), writable);
     }
 
-    config.syscall_filter(SyscallFilter::build(
-        args.allow_multiprocess,
-        args.allow_chmod,
-    ));
+    for socket in args.sockets {
+        let (host, sandbox) = match socket.split(',').collect::<Vec<_>>()[..] {
+            [host] => (host, host),
+            [host, sandbox] => (host, sandbox),
+            _ => bail!("Invalid socket: {}", socket),
+        };
+        config.socket(PathBuf::from(host), PathBuf::from(sandbox));
+    }
+
+    if args.auto_mount {
+        config.auto_mount()?;
+    }
+
+    let filter = match args.syscall_filter {
+        Some(path) => {
+            let file = std::fs::File::open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            serde_json::from_reader(file)
+                .with_context(|| format!("Invalid syscall filter in {}", path.display()))?
+        }
+        None => SyscallFilter::build(args.allow_multiprocess, args.allow_chmod),
+    };
+    config.syscall_filter(filter);
 
     trace!("Sandbox config {:#?}", config);
 
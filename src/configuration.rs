@@ -5,11 +5,41 @@
 
 //! Module that contains the configuration of the sandbox
 
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
 
 use crate::syscall_filter::SyscallFilter;
+use crate::Result;
+
+/// Options of a bind mount inside the sandbox
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MountOptions {
+    /// Do not allow programs to be executed from the mount
+    pub noexec: bool,
+
+    /// Ignore the set-user-ID and set-group-ID bits of the files in the mount
+    pub nosuid: bool,
+
+    /// Do not allow access to the device files in the mount
+    pub nodev: bool,
+
+    /// Bind-mount also the mounts below the source directory
+    pub recursive: bool,
+}
+
+impl Default for MountOptions {
+    fn default() -> Self {
+        MountOptions {
+            noexec: false,
+            nosuid: true,
+            nodev: true,
+            recursive: true,
+        }
+    }
+}
 
 /// Describes a mountpoint inside the sandbox
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -17,11 +47,178 @@ pub struct DirectoryMount {
     /// Where to mount the directory inside the sandbox.
     pub target: PathBuf,
 
-    /// Path of the directory to mount inside the sandbox
+    /// Path of the directory (or of the single file) to mount inside the sandbox
     pub source: PathBuf,
 
     /// Should the directory be writable or not
     pub writable: bool,
+
+    /// Options of the mount, applied both to read-only and to writable mounts
+    #[serde(default)]
+    pub options: MountOptions,
+}
+
+/// Describes a tmpfs mounted inside the sandbox
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TmpfsMount {
+    /// Where to mount the tmpfs inside the sandbox
+    pub target: PathBuf,
+
+    /// Maximum size of the tmpfs, in bytes
+    pub size: u64,
+
+    /// Maximum number of inodes of the tmpfs, `None` uses the kernel default
+    pub nr_inodes: Option<u64>,
+
+    /// Permissions of the root directory of the tmpfs
+    pub mode: u32,
+
+    /// Owner of the root directory of the tmpfs, as seen inside the sandbox. Since only the
+    /// sandbox user is mapped, this can only be `None` (the default) or the sandbox UID.
+    pub uid: Option<usize>,
+
+    /// Group of the root directory of the tmpfs, as seen inside the sandbox. Since only the
+    /// sandbox group is mapped, this can only be `None` (the default) or the sandbox GID.
+    pub gid: Option<usize>,
+}
+
+impl TmpfsMount {
+    /// A world-writable tmpfs at `target` of at most `size` bytes
+    pub fn new<P: Into<PathBuf>>(target: P, size: u64) -> Self {
+        TmpfsMount {
+            target: target.into(),
+            size,
+            nr_inodes: None,
+            mode: 0o1777,
+            uid: None,
+            gid: None,
+        }
+    }
+}
+
+/// Describes an overlay mounted inside the sandbox: the program sees the content of a directory
+/// of the host and can modify it, but the changes are stored in a separate layer of the sandbox,
+/// leaving the host directory untouched.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OverlayMount {
+    /// Where to mount the overlay inside the sandbox
+    pub target: PathBuf,
+
+    /// Path of the read-only directory with the original content
+    pub lower: PathBuf,
+
+    /// Copy the files created or modified by the program into this directory of the host
+    pub copy_changes_to: Option<PathBuf>,
+
+    /// Maximum size in bytes of the files created or modified by the program, which are stored
+    /// in a tmpfs
+    pub size: u64,
+}
+
+impl OverlayMount {
+    /// An overlay at `target` over the content of `lower`
+    pub fn new<P, Q>(lower: P, target: Q) -> Self
+    where
+        P: Into<PathBuf>,
+        Q: Into<PathBuf>,
+    {
+        OverlayMount {
+            target: target.into(),
+            lower: lower.into(),
+            copy_changes_to: None,
+            size: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// Describes a unix socket of the host made reachable inside the sandbox
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SocketMount {
+    /// Path of the socket on the host
+    pub source: PathBuf,
+
+    /// Where the socket is available inside the sandbox
+    pub target: PathBuf,
+}
+
+/// A file created inside the sandbox with the specified content
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InlineFile {
+    /// Where to create the file inside the sandbox
+    pub target: PathBuf,
+
+    /// Content of the file
+    pub contents: Vec<u8>,
+
+    /// Permissions of the file
+    pub mode: u32,
+}
+
+/// Content of the root filesystem of the sandbox
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RootFilesystem {
+    /// Use this directory as the root, read-only
+    Directory(PathBuf),
+
+    /// Extract this tar archive in the root
+    Tarball(PathBuf),
+}
+
+/// Options of the /proc mounted inside the sandbox
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ProcOptions {
+    /// Hide the processes of the other users (`hidepid=2`)
+    pub hide_pids: bool,
+
+    /// Show only the process directories, without any system information (`subset=pid`,
+    /// requires Linux 5.8)
+    pub only_pids: bool,
+
+    /// Hide the sensitive entries (like `/proc/kcore` and `/proc/sysrq-trigger`) and make the
+    /// kernel settings (like `/proc/sys`) read-only
+    pub mask_sensitive: bool,
+}
+
+impl Default for ProcOptions {
+    fn default() -> Self {
+        ProcOptions {
+            hide_pids: false,
+            only_pids: false,
+            mask_sensitive: true,
+        }
+    }
+}
+
+/// Which part of sysfs to show inside the sandbox, always read-only
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SysfsMount {
+    /// The whole sysfs
+    Full,
+
+    /// Only `/sys/devices/system/cpu`, with the CPU topology
+    CpuTopology,
+}
+
+/// Offsets of the clocks of the time namespace of the sandbox, in seconds. They are added to the
+/// clocks of the host, the resulting values cannot be negative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ClockOffsets {
+    /// Offset of `CLOCK_MONOTONIC`
+    pub monotonic: i64,
+
+    /// Offset of `CLOCK_BOOTTIME`
+    pub boottime: i64,
+}
+
+/// Network access of the sandbox
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Network {
+    /// No network interface at all
+    #[default]
+    None,
+
+    /// Only the loopback interface, isolated from the one of the host
+    Loopback,
 }
 
 /// struct that represents the configuration parameters
@@ -40,12 +237,22 @@ pub struct SandboxConfiguration {
     /// Absolute path of the executable
     pub executable: PathBuf,
 
+    /// Open the executable from the host before entering the sandbox, so that it doesn't have to
+    /// be mounted inside (supported only on Linux). Only ELF executables are supported, not
+    /// scripts, and the syscall filter must allow `execveat`, which is used for starting them.
+    #[serde(default)]
+    pub exec_from_host: bool,
+
     /// Arguments to pass to the executable
     pub args: Vec<String>,
 
     /// Environment to pass to the sandbox
     pub env: Vec<(String, String)>,
 
+    /// Content of the root of the sandbox, by default it's empty
+    #[serde(default)]
+    pub rootfs: Option<RootFilesystem>,
+
     /// Allowed paths inside the sandbox
     pub mount_paths: Vec<DirectoryMount>,
 
@@ -61,12 +268,55 @@ pub struct SandboxConfiguration {
     /// Redirect stderr from this file
     pub stderr: Option<PathBuf>,
 
+    /// Extra file descriptors to pass to the sandbox in the form of (host_fd, sandbox_fd)
+    /// (supported only on Linux)
+    #[serde(default)]
+    pub extra_fds: Vec<(RawFd, RawFd)>,
+
     /// Allow only these system calls in the sandbox
     pub syscall_filter: Option<SyscallFilter>,
 
     /// Mount a r/w tmpfs in /tmp and /dev/shm
     pub mount_tmpfs: bool,
 
+    /// Unix sockets of the host to bind inside the sandbox
+    #[serde(default)]
+    pub socket_mounts: Vec<SocketMount>,
+
+    /// Device nodes of the host to make available in /dev, relative to /dev
+    #[serde(default = "default_devices")]
+    pub devices: Vec<String>,
+
+    /// Writable overlays over read-only directories, the changes are reported in the result
+    #[serde(default)]
+    pub overlay_mounts: Vec<OverlayMount>,
+
+    /// Additional tmpfs to mount inside the sandbox, after the other mount points
+    #[serde(default)]
+    pub tmpfs_mounts: Vec<TmpfsMount>,
+
+    /// Files to create inside the sandbox, after all the mount points are mounted. They are
+    /// usually created in the root tmpfs, but a file inside a writable mount is written there.
+    #[serde(default)]
+    pub files: Vec<InlineFile>,
+
+    /// Size in bytes of the tmpfs used as the root of the sandbox
+    #[serde(default = "default_root_tmpfs_size")]
+    pub root_tmpfs_size: u64,
+
+    /// Glob patterns (supporting `*`, `?` and `**`) of the files to collect after the execution.
+    /// Only the regular files inside the writable tmpfs mounts are collected.
+    #[serde(default)]
+    pub collect_files: Vec<String>,
+
+    /// Copy the collected files into this directory, instead of returning their content
+    #[serde(default)]
+    pub collect_directory: Option<PathBuf>,
+
+    /// Maximum total size of the collected files, in bytes. `None` for no limit.
+    #[serde(default = "default_collect_size_limit")]
+    pub collect_size_limit: Option<u64>,
+
     /// Wall time limit
     pub wall_time_limit: Option<u64>,
 
@@ -81,6 +331,54 @@ pub struct SandboxConfiguration {
 
     /// Mount /proc
     pub mount_proc: bool,
+
+    /// Options of /proc, when it's mounted
+    #[serde(default)]
+    pub proc_options: ProcOptions,
+
+    /// Mount /sys
+    #[serde(default)]
+    pub mount_sys: Option<SysfsMount>,
+
+    /// Hostname inside the sandbox, by default it's the one of the host
+    #[serde(default)]
+    pub hostname: Option<String>,
+
+    /// Network interfaces available inside the sandbox
+    #[serde(default)]
+    pub network: Network,
+
+    /// Run the sandbox in a new time namespace with these clock offsets (requires Linux 5.6)
+    #[serde(default)]
+    pub time_namespace: Option<ClockOffsets>,
+
+    /// Make the execution as reproducible as possible: disable ASLR, fix the hostname and pin the
+    /// sandbox to a single CPU core (supported only on Linux)
+    #[serde(default)]
+    pub deterministic: bool,
+
+    /// Generate minimal /etc/passwd, /etc/group, /etc/hosts and /etc/hostname files for the
+    /// sandbox user, hiding the ones of the host
+    #[serde(default)]
+    pub generate_etc: bool,
+}
+
+/// Devices of the host available by default in the sandbox
+fn default_devices() -> Vec<String> {
+    ["null", "zero", "random", "urandom"]
+        .iter()
+        .map(|device| device.to_string())
+        .collect()
+}
+
+/// Default size of the tmpfs at the root of the sandbox
+fn default_root_tmpfs_size() -> u64 {
+    256 * 1024 * 1024
+}
+
+/// Default limit of the total size of the collected files
+fn default_collect_size_limit() -> Option<u64> {
+    Some(64 * 1024 * 1024)
 }
 
 impl Default for SandboxConfiguration {
@@ -90,20 +388,39 @@ impl Default for SandboxConfiguration {
             memory_limit: None,
             stack_limit: None,
             executable: PathBuf::from("/bin/sh"),
+            exec_from_host: false,
             args: vec![],
             env: vec![],
+            rootfs: None,
             mount_paths: vec![],
             working_directory: PathBuf::from("/"),
             stdin: None,
             stdout: None,
             stderr: None,
+            extra_fds: vec![],
             syscall_filter: None,
             mount_tmpfs: false,
+            socket_mounts: vec![],
+            devices: default_devices(),
+            overlay_mounts: vec![],
+            tmpfs_mounts: vec![],
+            files: vec![],
+            root_tmpfs_size: default_root_tmpfs_size(),
+            collect_files: vec![],
+            collect_directory: None,
+            collect_size_limit: default_collect_size_limit(),
             wall_time_limit: None,
             cpu_core: None,
             uid: 0,
             gid: 0,
             mount_proc: false,
+            proc_options: ProcOptions::default(),
+            mount_sys: None,
+            hostname: None,
+            network: Network::default(),
+            time_namespace: None,
+            deterministic: false,
+            generate_etc: false,
         }
     }
 }
@@ -150,12 +467,24 @@ impl SandboxConfiguration {
         self
     }
 
+    /// Pass the file descriptor `host_fd` to the sandbox, available there as `sandbox_fd`
+    pub fn extra_fd(&mut self, host_fd: RawFd, sandbox_fd: RawFd) -> &mut Self {
+        self.extra_fds.push((host_fd, sandbox_fd));
+        self
+    }
+
     /// Set the executable file path
     pub fn executable<P: Into<PathBuf>>(&mut self, executable: P) -> &mut Self {
         self.executable = executable.into();
         self
     }
 
+    /// Execute the executable of the host, without mounting it inside the sandbox
+    pub fn exec_from_host(&mut self, exec_from_host: bool) -> &mut Self {
+        self.exec_from_host = exec_from_host;
+        self
+    }
+
     /// Set the working directory
     pub fn working_directory<P: Into<PathBuf>>(&mut self, working_directory: P) -> &mut Self {
         self.working_directory = working_directory.into();
@@ -174,8 +503,29 @@ impl SandboxConfiguration {
         self
     }
 
+    /// Set the content of the root of the sandbox
+    pub fn rootfs(&mut self, rootfs: RootFilesystem) -> &mut Self {
+        self.rootfs = Some(rootfs);
+        self
+    }
+
     /// Add a mount point into the sandbox
     pub fn mount<P, Q>(&mut self, source: P, target: Q, writable: bool) -> &mut Self
+    where
+        P: Into<PathBuf>,
+        Q: Into<PathBuf>,
+    {
+        self.mount_with_options(source, target, writable, MountOptions::default())
+    }
+
+    /// Add a mount point into the sandbox, with the specified mount options
+    pub fn mount_with_options<P, Q>(
+        &mut self,
+        source: P,
+        target: Q,
+        writable: bool,
+        options: MountOptions,
+    ) -> &mut Self
     where
         P: Into<PathBuf>,
         Q: Into<PathBuf>,
@@ -184,10 +534,29 @@ impl SandboxConfiguration {
             source: source.into(),
             target: target.into(),
             writable,
+            options,
         });
         self
     }
 
+    /// Add read-only mounts for the executable, its dynamic loader and the shared libraries it
+    /// needs, searched like the dynamic loader of the host does. The files already inside a
+    /// mounted directory are skipped.
+    pub fn auto_mount(&mut self) -> Result<&mut Self> {
+        let files = crate::elf::dependencies(&self.executable)
+            .with_context(|| format!("Failed to analyze {}", self.executable.display()))?;
+        for file in files {
+            let mounted = self
+                .mount_paths
+                .iter()
+                .any(|mount| mount.source == mount.target && file.starts_with(&mount.target));
+            if !mounted {
+                self.mount(file.clone(), file, false);
+            }
+        }
+        Ok(self)
+    }
+
     /// Install the syscall filter
     pub fn syscall_filter(&mut self, filter: SyscallFilter) -> &mut Self {
         self.syscall_filter = Some(filter);
@@ -200,6 +569,84 @@ impl SandboxConfiguration {
         self
     }
 
+    /// Make the unix socket `source` of the host reachable at `target` inside the sandbox
+    pub fn socket<P, Q>(&mut self, source: P, target: Q) -> &mut Self
+    where
+        P: Into<PathBuf>,
+        Q: Into<PathBuf>,
+    {
+        self.socket_mounts.push(SocketMount {
+            source: source.into(),
+            target: target.into(),
+        });
+        self
+    }
+
+    /// Make the host device `/dev/<name>` available inside the sandbox
+    pub fn device<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        let name = name.into();
+        if !self.devices.contains(&name) {
+            self.devices.push(name);
+        }
+        self
+    }
+
+    /// Remove the device `/dev/<name>` from the ones available inside the sandbox
+    pub fn remove_device(&mut self, name: &str) -> &mut Self {
+        self.devices.retain(|device| device != name);
+        self
+    }
+
+    /// Mount an overlay inside the sandbox
+    pub fn overlay(&mut self, mount: OverlayMount) -> &mut Self {
+        self.overlay_mounts.push(mount);
+        self
+    }
+
+    /// Mount a tmpfs inside the sandbox
+    pub fn tmpfs(&mut self, mount: TmpfsMount) -> &mut Self {
+        self.tmpfs_mounts.push(mount);
+        self
+    }
+
+    /// Create a file inside the sandbox with the specified content and permissions
+    pub fn file<P, C>(&mut self, target: P, contents: C, mode: u32) -> &mut Self
+    where
+        P: Into<PathBuf>,
+        C: Into<Vec<u8>>,
+    {
+        self.files.push(InlineFile {
+            target: target.into(),
+            contents: contents.into(),
+            mode,
+        });
+        self
+    }
+
+    /// Set the size of the root tmpfs, in **bytes**
+    pub fn root_tmpfs_size(&mut self, size: u64) -> &mut Self {
+        self.root_tmpfs_size = size;
+        self
+    }
+
+    /// Collect the files matching the glob pattern after the execution
+    pub fn collect<S: Into<String>>(&mut self, pattern: S) -> &mut Self {
+        self.collect_files.push(pattern.into());
+        self
+    }
+
+    /// Copy the collected files into this directory
+    pub fn collect_directory<P: Into<PathBuf>>(&mut self, directory: P) -> &mut Self {
+        self.collect_directory = Some(directory.into());
+        self
+    }
+
+    /// Set the maximum total size of the collected files, in **bytes**
+    pub fn collect_size_limit(&mut self, limit: u64) -> &mut Self {
+        self.collect_size_limit = Some(limit);
+        self
+    }
+
     /// Set wall time limit
     pub fn wall_time_limit(&mut self, value: u64) -> &mut Self {
         self.wall_time_limit = Some(value);
@@ -229,4 +676,83 @@ impl SandboxConfiguration {
         self.mount_proc = mount_proc;
         self
     }
+
+    /// Set the options of /proc
+    pub fn proc_options(&mut self, options: ProcOptions) -> &mut Self {
+        self.proc_options = options;
+        self
+    }
+
+    /// Mount a read-only /sys
+    pub fn mount_sys(&mut self, mount: SysfsMount) -> &mut Self {
+        self.mount_sys = Some(mount);
+        self
+    }
+
+    /// Set the hostname inside the sandbox
+    pub fn hostname<S: Into<String>>(&mut self, hostname: S) -> &mut Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Set the network interfaces available inside the sandbox
+    pub fn network(&mut self, network: Network) -> &mut Self {
+        self.network = network;
+        self
+    }
+
+    /// Run the sandbox in a new time namespace, shifting its clocks by the specified offsets
+    pub fn time_namespace(&mut self, offsets: ClockOffsets) -> &mut Self {
+        self.time_namespace = Some(offsets);
+        self
+    }
+
+    /// Enable the deterministic mode
+    pub fn deterministic(&mut self, deterministic: bool) -> &mut Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Generate the user, group and host files in /etc
+    pub fn generate_etc(&mut self, generate_etc: bool) -> &mut Self {
+        self.generate_etc = generate_etc;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_without_new_fields() {
+        let json = r#"{
+            "time_limit": 1,
+            "memory_limit": null,
+            "stack_limit": null,
+            "executable": "/bin/true",
+            "args": [],
+            "env": [],
+            "mount_paths": [{"target": "/usr", "source": "/usr", "writable": false}],
+            "working_directory": "/",
+            "stdin": null,
+            "stdout": null,
+            "stderr": null,
+            "syscall_filter": null,
+            "mount_tmpfs": true,
+            "wall_time_limit": null,
+            "cpu_core": null,
+            "uid": 0,
+            "gid": 0,
+            "mount_proc": false
+        }"#;
+        let config: SandboxConfiguration = serde_json::from_str(json).unwrap();
+        let default = SandboxConfiguration::default();
+        assert_eq!(config.time_limit, Some(1));
+        assert_eq!(config.mount_paths[0].options, MountOptions::default());
+        assert_eq!(config.devices, default.devices);
+        assert_eq!(config.root_tmpfs_size, default.root_tmpfs_size);
+        assert_eq!(config.collect_size_limit, default.collect_size_limit);
+        assert_eq!(config.network, Network::None);
+    }
 }
@@ -0,0 +1,421 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+// SPDX-License-Identifier: MPL-2.0
+
+//! Discovery of the files needed for running a dynamically linked ELF executable
+
+use std::collections::{HashSet, VecDeque};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+
+use crate::util::wildcard_match;
+use crate::Result;
+
+/// Segment with the path of the dynamic loader
+const PT_INTERP: u32 = 3;
+/// Segment with the dynamic section
+const PT_DYNAMIC: u32 = 2;
+/// Segment loaded in memory
+const PT_LOAD: u32 = 1;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+/// Configuration of the search paths of the dynamic loader
+const LD_SO_CONF: &str = "/etc/ld.so.conf";
+/// Cache of the libraries, used by the dynamic loader for the directories of `LD_SO_CONF`
+const LD_SO_CACHE: &str = "/etc/ld.so.cache";
+/// Directories searched by the dynamic loader after the configured ones
+const DEFAULT_DIRS: &[&str] = &["/lib64", "/usr/lib64", "/lib", "/usr/lib"];
+
+/// Information about the dynamic linking of an ELF file
+#[derive(Debug)]
+struct ElfInfo {
+    /// `true` for 64-bit files
+    is_64: bool,
+    /// Target architecture of the file
+    machine: u16,
+    /// Path of the dynamic loader, only for executables
+    interpreter: Option<PathBuf>,
+    /// Names of the needed libraries
+    needed: Vec<Vec<u8>>,
+    /// Search paths of the libraries, the old format
+    rpath: Vec<Vec<u8>>,
+    /// Search paths of the libraries, they disable `rpath` if present
+    runpath: Vec<Vec<u8>>,
+}
+
+/// A parsed ELF file
+struct Elf<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    little_endian: bool,
+}
+
+/// A program header of an ELF file
+struct ProgramHeader {
+    kind: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+/// List the files needed for running `executable`: the executable itself, its dynamic loader and
+/// all the shared libraries it depends on, searched like the dynamic loader of the host does.
+pub fn dependencies(executable: &Path) -> Result<Vec<PathBuf>> {
+    let info = ElfInfo::read(executable)?;
+    let mut files = vec![executable.to_owned()];
+    let interpreter = match &info.interpreter {
+        Some(interpreter) => interpreter.clone(),
+        // Statically linked, nothing else is needed
+        None => return Ok(files),
+    };
+    files.push(interpreter.clone());
+
+    let system_dirs = system_dirs();
+    // The RPATH of the executable applies also to the libraries without a RUNPATH
+    let executable_rpath = expand_paths(&info.rpath, executable);
+    let mut found = HashSet::new();
+    found.insert(interpreter);
+    let mut queue = VecDeque::from([(executable.to_owned(), info)]);
+    while let Some((path, info)) = queue.pop_front() {
+        let mut dirs = vec![];
+        if info.runpath.is_empty() {
+            dirs.extend(expand_paths(&info.rpath, &path));
+            dirs.extend(executable_rpath.iter().cloned());
+        }
+        dirs.extend(expand_paths(&info.runpath, &path));
+        dirs.extend(system_dirs.iter().cloned());
+
+        for name in &info.needed {
+            let name = Path::new(OsStr::from_bytes(name));
+            let library = find_library(name, &dirs, &info).with_context(|| {
+                format!(
+                    "Library {} needed by {} not found",
+                    name.display(),
+                    path.display()
+                )
+            })?;
+            if found.insert(library.clone()) {
+                let library_info = ElfInfo::read(&library)?;
+                files.push(library.clone());
+                queue.push_back((library, library_info));
+            }
+        }
+    }
+
+    if Path::new(LD_SO_CACHE).exists() {
+        files.push(PathBuf::from(LD_SO_CACHE));
+    }
+    Ok(files)
+}
+
+/// Find a library in the search directories, skipping the ones for other architectures
+fn find_library(name: &Path, dirs: &[PathBuf], object: &ElfInfo) -> Option<PathBuf> {
+    // A name with a slash is a path, used as it is by the dynamic loader
+    if name.components().count() > 1 {
+        return (name.is_absolute() && is_compatible(name, object)).then(|| name.to_owned());
+    }
+    dirs.iter()
+        .map(|dir| dir.join(name))
+        .find(|path| is_compatible(path, object))
+}
+
+/// Check that the file is an ELF for the same architecture of `object`
+fn is_compatible(path: &Path, object: &ElfInfo) -> bool {
+    let mut header = [0u8; 20];
+    let read = File::open(path).and_then(|mut file| file.read_exact(&mut header));
+    if read.is_err() {
+        return false;
+    }
+    match Elf::parse(&header) {
+        Ok(elf) => elf.is_64 == object.is_64 && elf.machine().ok() == Some(object.machine),
+        Err(_) => false,
+    }
+}
+
+/// Directories where the dynamic loader of the host searches the libraries
+fn system_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    read_ld_so_conf(Path::new(LD_SO_CONF), &mut dirs);
+    dirs.extend(DEFAULT_DIRS.iter().map(PathBuf::from));
+    dirs
+}
+
+/// Parse a configuration file of the dynamic loader, following the `include` directives
+fn read_ld_so_conf(path: &Path, dirs: &mut Vec<PathBuf>) {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return,
+    };
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if let Some(pattern) = line.strip_prefix("include") {
+            let pattern = Path::new("/etc").join(pattern.trim());
+            let (dir, name) = match (pattern.parent(), pattern.file_name()) {
+                (Some(dir), Some(name)) => (dir, name),
+                _ => continue,
+            };
+            let mut files: Vec<_> = fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter(|entry| wildcard_match(name.as_bytes(), entry.file_name().as_bytes()))
+                .map(|entry| entry.path())
+                .collect();
+            files.sort();
+            for file in files {
+                read_ld_so_conf(&file, dirs);
+            }
+        } else if line.starts_with('/') {
+            dirs.push(PathBuf::from(line));
+        }
+    }
+}
+
+/// Split the search paths of an object, replacing `$ORIGIN` with its directory
+fn expand_paths(paths: &[Vec<u8>], object: &Path) -> Vec<PathBuf> {
+    let origin = object.parent().unwrap_or_else(|| Path::new("/"));
+    let origin = origin.as_os_str().to_string_lossy();
+    paths
+        .iter()
+        .flat_map(|paths| paths.split(|c| *c == b':'))
+        .filter(|path| !path.is_empty())
+        .map(|path| {
+            let path = String::from_utf8_lossy(path)
+                .replace("${ORIGIN}", &origin)
+                .replace("$ORIGIN", &origin);
+            PathBuf::from(path)
+        })
+        .collect()
+}
+
+/// Add a displacement to an offset in the file, failing if it overflows
+fn add_offset(offset: u64, displacement: u64) -> Result<u64> {
+    offset
+        .checked_add(displacement)
+        .context("Truncated ELF file")
+}
+
+impl ElfInfo {
+    /// Read the dynamic linking information of an ELF file
+    fn read(path: &Path) -> Result<ElfInfo> {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        ElfInfo::parse(&data).with_context(|| format!("Failed to parse ELF {}", path.display()))
+    }
+
+    fn parse(data: &[u8]) -> Result<ElfInfo> {
+        let elf = Elf::parse(data)?;
+        let headers = elf.program_headers()?;
+        let mut info = ElfInfo {
+            is_64: elf.is_64,
+            machine: elf.machine()?,
+            interpreter: None,
+            needed: vec![],
+            rpath: vec![],
+            runpath: vec![],
+        };
+        if let Some(interp) = headers.iter().find(|h| h.kind == PT_INTERP) {
+            let interp = elf.string(interp.offset)?;
+            info.interpreter = Some(PathBuf::from(OsStr::from_bytes(interp)));
+        }
+        let dynamic = match headers.iter().find(|h| h.kind == PT_DYNAMIC) {
+            Some(dynamic) => dynamic,
+            None => return Ok(info),
+        };
+
+        let word = if elf.is_64 { 8 } else { 4 };
+        let mut entries = vec![];
+        let end = add_offset(dynamic.offset, dynamic.filesz)?;
+        let mut offset = dynamic.offset;
+        loop {
+            let next = add_offset(offset, 2 * word)?;
+            if next > end {
+                break;
+            }
+            let tag = elf.read(offset, word)?;
+            if tag == DT_NULL {
+                break;
+            }
+            entries.push((tag, elf.read(add_offset(offset, word)?, word)?));
+            offset = next;
+        }
+
+        // The string table is referenced by its address in memory
+        let strtab = match entries.iter().find(|(tag, _)| *tag == DT_STRTAB) {
+            Some((_, address)) => {
+                let segment = headers
+                    .iter()
+                    .filter(|h| h.kind == PT_LOAD)
+                    .find(|h| h.vaddr <= *address && *address - h.vaddr < h.filesz)
+                    .context("String table outside of the loaded segments")?;
+                add_offset(address - segment.vaddr, segment.offset)?
+            }
+            None => bail!("Dynamic section without a string table"),
+        };
+        for (tag, value) in entries {
+            let list = match tag {
+                DT_NEEDED => &mut info.needed,
+                DT_RPATH => &mut info.rpath,
+                DT_RUNPATH => &mut info.runpath,
+                _ => continue,
+            };
+            list.push(elf.string(add_offset(strtab, value)?)?.to_vec());
+        }
+        Ok(info)
+    }
+}
+
+impl<'a> Elf<'a> {
+    /// Check the identification of the ELF file
+    fn parse(data: &'a [u8]) -> Result<Elf<'a>> {
+        if data.len() < 20 || &data[0..4] != b"\x7fELF" {
+            bail!("Not an ELF file");
+        }
+        let is_64 = match data[4] {
+            1 => false,
+            2 => true,
+            class => bail!("Invalid ELF class {}", class),
+        };
+        let little_endian = match data[5] {
+            1 => true,
+            2 => false,
+            encoding => bail!("Invalid ELF data encoding {}", encoding),
+        };
+        Ok(Elf {
+            data,
+            is_64,
+            little_endian,
+        })
+    }
+
+    fn machine(&self) -> Result<u16> {
+        Ok(self.read(0x12, 2)? as u16)
+    }
+
+    fn program_headers(&self) -> Result<Vec<ProgramHeader>> {
+        let (phoff, phentsize, phnum) = if self.is_64 {
+            (
+                self.read(0x20, 8)?,
+                self.read(0x36, 2)?,
+                self.read(0x38, 2)?,
+            )
+        } else {
+            (
+                self.read(0x1c, 4)?,
+                self.read(0x2a, 2)?,
+                self.read(0x2c, 2)?,
+            )
+        };
+        (0..phnum)
+            .map(|index| {
+                let base = add_offset(
+                    phoff,
+                    index.checked_mul(phentsize).context("Truncated ELF file")?,
+                )?;
+                Ok(if self.is_64 {
+                    ProgramHeader {
+                        kind: self.read(base, 4)? as u32,
+                        offset: self.read(add_offset(base, 8)?, 8)?,
+                        vaddr: self.read(add_offset(base, 16)?, 8)?,
+                        filesz: self.read(add_offset(base, 32)?, 8)?,
+                    }
+                } else {
+                    ProgramHeader {
+                        kind: self.read(base, 4)? as u32,
+                        offset: self.read(add_offset(base, 4)?, 4)?,
+                        vaddr: self.read(add_offset(base, 8)?, 4)?,
+                        filesz: self.read(add_offset(base, 16)?, 4)?,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Read an integer of `size` bytes at `offset`
+    fn read(&self, offset: u64, size: u64) -> Result<u64> {
+        let bytes = usize::try_from(offset)
+            .ok()
+            .and_then(|start| self.data.get(start..start.checked_add(size as usize)?))
+            .context("Truncated ELF file")?;
+        let fold = |acc: u64, b: &u8| (acc << 8) | u64::from(*b);
+        Ok(if self.little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        })
+    }
+
+    /// Read a NUL-terminated string at `offset`
+    fn string(&self, offset: u64) -> Result<&'a [u8]> {
+        let data = usize::try_from(offset)
+            .ok()
+            .and_then(|start| self.data.get(start..))
+            .context("Truncated ELF file")?;
+        match data.iter().position(|b| *b == 0) {
+            Some(end) => Ok(&data[..end]),
+            None => bail!("Unterminated string in ELF file"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{dependencies, find_library, ElfInfo};
+
+    #[test]
+    fn test_dependencies() {
+        // The test binary is dynamically linked with the C library
+        let executable = std::env::current_exe().unwrap();
+        let files = dependencies(&executable).unwrap();
+        assert_eq!(files[0], executable);
+        assert!(files.len() > 2, "{:?}", files);
+        assert!(files
+            .iter()
+            .any(|file| file.to_string_lossy().contains("libc.so")));
+        assert!(files.iter().all(|file| file.exists()), "{:?}", files);
+    }
+
+    #[test]
+    fn test_find_library_path() {
+        let executable = std::env::current_exe().unwrap();
+        let info = ElfInfo::read(&executable).unwrap();
+        let libc = dependencies(&executable)
+            .unwrap()
+            .into_iter()
+            .find(|file| file.to_string_lossy().contains("libc.so"))
+            .unwrap();
+        assert_eq!(find_library(&libc, &[], &info), Some(libc.clone()));
+        // Relative paths, missing files and files that are not libraries are rejected
+        let relative = Path::new("lib/libc.so.6");
+        assert_eq!(find_library(relative, &[], &info), None);
+        assert_eq!(
+            find_library(Path::new("/missing/libc.so.6"), &[], &info),
+            None
+        );
+        assert_eq!(find_library(Path::new("/etc/passwd"), &[], &info), None);
+    }
+
+    #[test]
+    fn test_overflowing_offsets() {
+        let mut data = vec![0u8; 64];
+        data[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        // Program headers at the end of the address space
+        data[0x20..0x28].copy_from_slice(&u64::MAX.to_le_bytes());
+        data[0x36] = 56;
+        data[0x38] = 1;
+        let err = ElfInfo::parse(&data).unwrap_err();
+        assert_eq!(err.to_string(), "Truncated ELF file");
+    }
+}
@@ -25,6 +25,7 @@ pub mod configuration;
 pub mod result;
 pub mod syscall_filter;
 
+mod elf;
 mod util;
 
 #[cfg(target_os = "linux")]
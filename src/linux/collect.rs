@@ -0,0 +1,359 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use nix::fcntl::OFlag;
+use nix::sched::{setns, CloneFlags};
+use nix::unistd;
+
+use crate::configuration::SandboxConfiguration;
+use crate::result::CollectedFile;
+use crate::util::{strerror, wildcard_match};
+use crate::Result;
+
+/// The namespaces of the sandbox, kept open for accessing its filesystem after the program has
+/// exited: as long as a reference to the mount namespace exists, its mounts are kept alive.
+pub struct SandboxNamespaces {
+    user: File,
+    mount: File,
+}
+
+impl SandboxNamespaces {
+    /// Open the namespaces of the process with the specified PID
+    pub fn open(pid: libc::pid_t) -> Result<Self> {
+        let open = |name: &str| {
+            let path = format!("/proc/{}/ns/{}", pid, name);
+            File::open(&path).with_context(|| format!("Failed to open {}", path))
+        };
+        Ok(SandboxNamespaces {
+            user: open("user")?,
+            mount: open("mnt")?,
+        })
+    }
+
+    /// Execute `send` in a new process that joined the namespaces of the sandbox, with the root
+    /// directory set to the root of the sandbox, and `receive` in this process for reading the data
+    /// it writes. `chroot` is the path of the sandbox root inside the mount namespace, if it's not
+    /// the root of the namespace.
+    pub fn run<T, S, R>(&self, chroot: Option<&Path>, send: S, receive: R) -> Result<T>
+    where
+        S: FnOnce(&mut UnixStream) -> Result<()>,
+        R: FnOnce(&mut UnixStream) -> Result<T>,
+    {
+        let (mut output, mut input) = UnixStream::pair().context("Failed to create socket")?;
+        // The error of the helper process has its own pipe, since it can happen in the middle of
+        // the data
+        let (error_output, error_input) =
+            unistd::pipe2(OFlag::O_CLOEXEC).context("Failed to create pipe")?;
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            bail!("fork() error: {}", strerror());
+        }
+
+        if pid == 0 {
+            drop(output);
+            drop(error_output);
+            let status = match self.enter(chroot).and_then(|_| send(&mut input)) {
+                Ok(()) => 0,
+                Err(e) => {
+                    let _ = File::from(error_input).write_all(format!("{:?}", e).as_bytes());
+                    1
+                }
+            };
+            // Do not run any destructor of the parent process
+            unsafe { libc::_exit(status) };
+        }
+
+        drop(input);
+        drop(error_input);
+        let received = receive(&mut output);
+        // Unblock the helper process if it's still writing
+        drop(output);
+        let mut error = String::new();
+        let read = File::from(error_output).read_to_string(&mut error);
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid, &mut status, 0) } != pid {
+            bail!("Error waiting for the helper process: {}", strerror());
+        }
+        read.context("Failed to read the error of the helper process")?;
+        if !error.is_empty() {
+            return Err(anyhow!(error));
+        }
+        let received = received?;
+        if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+            bail!("The helper process failed with status {}", status);
+        }
+        Ok(received)
+    }
+
+    /// Join the namespaces and enter the sandbox root. Must be called by a single-threaded process.
+    fn enter(&self, chroot: Option<&Path>) -> Result<()> {
+        setns(&self.user, CloneFlags::CLONE_NEWUSER).context("Failed to join user namespace")?;
+        setns(&self.mount, CloneFlags::CLONE_NEWNS).context("Failed to join mount namespace")?;
+        if let Some(chroot) = chroot {
+            unistd::chroot(chroot).context("Failed to chroot")?;
+        }
+        unistd::chdir("/").context("Failed to chdir")?;
+        Ok(())
+    }
+}
+
+/// Send a file descriptor over a unix socket
+pub fn send_fd(socket: &UnixStream, fd: RawFd) -> Result<()> {
+    let mut data = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut control = vec![0u8; fd_control_space()];
+    let message = fd_message(&mut iov, &mut control);
+    unsafe {
+        let header = libc::CMSG_FIRSTHDR(&message);
+        (*header).cmsg_level = libc::SOL_SOCKET;
+        (*header).cmsg_type = libc::SCM_RIGHTS;
+        (*header).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(header) as *mut RawFd, fd);
+    }
+    if unsafe { libc::sendmsg(socket.as_raw_fd(), &message, 0) } < 0 {
+        bail!("sendmsg() error: {}", strerror());
+    }
+    Ok(())
+}
+
+/// Receive a file descriptor sent with `send_fd`
+pub fn receive_fd(socket: &UnixStream) -> Result<OwnedFd> {
+    let mut data = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut control = vec![0u8; fd_control_space()];
+    let mut message = fd_message(&mut iov, &mut control);
+    let received =
+        unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, libc::MSG_CMSG_CLOEXEC) };
+    if received < 0 {
+        bail!("recvmsg() error: {}", strerror());
+    }
+    unsafe {
+        let header = libc::CMSG_FIRSTHDR(&message);
+        if received == 0
+            || header.is_null()
+            || (*header).cmsg_level != libc::SOL_SOCKET
+            || (*header).cmsg_type != libc::SCM_RIGHTS
+        {
+            bail!("No file descriptor received");
+        }
+        let fd = std::ptr::read_unaligned(libc::CMSG_DATA(header) as *const RawFd);
+        Ok(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+/// Size of the control data of a message with a file descriptor
+fn fd_control_space() -> usize {
+    unsafe { libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) as usize }
+}
+
+/// A message with the data in `iov` and the control data in `control`
+fn fd_message(iov: &mut libc::iovec, control: &mut [u8]) -> libc::msghdr {
+    let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+    message.msg_iov = iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = control.len() as _;
+    message
+}
+
+/// Collect the files matching the patterns of the configuration from the writable tmpfs mounts
+/// of the sandbox. Returns the files and whether some of them were skipped for exceeding the size
+/// limit.
+pub fn collect_files(
+    config: &SandboxConfiguration,
+    namespaces: &SandboxNamespaces,
+    chroot: Option<&Path>,
+) -> Result<(Vec<CollectedFile>, bool)> {
+    namespaces.run(
+        chroot,
+        |output| send_files(config, output),
+        |input| receive_files(config, input),
+    )
+}
+
+/// Send the list of the files to collect, followed by their contents, from inside the sandbox.
+/// The list is encoded in JSON and prefixed by its length.
+fn send_files(config: &SandboxConfiguration, output: &mut UnixStream) -> Result<()> {
+    let (files, truncated) = find_files(config)?;
+    let header = serde_json::to_vec(&(&files, truncated))?;
+    output.write_all(&(header.len() as u64).to_le_bytes())?;
+    output.write_all(&header)?;
+    for file in &files {
+        let source = File::open(&file.path)
+            .with_context(|| format!("Failed to open {}", file.path.display()))?;
+        let sent = io::copy(&mut source.take(file.size), output)
+            .with_context(|| format!("Failed to send {}", file.path.display()))?;
+        if sent != file.size {
+            bail!("{} changed while collecting it", file.path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Receive the files sent by `send_files`, copying their contents into the collect directory if
+/// it's set
+fn receive_files(
+    config: &SandboxConfiguration,
+    input: &mut UnixStream,
+) -> Result<(Vec<CollectedFile>, bool)> {
+    let mut length = [0; 8];
+    input
+        .read_exact(&mut length)
+        .context("Failed to read the list of collected files")?;
+    let mut header = vec![0; u64::from_le_bytes(length) as usize];
+    input
+        .read_exact(&mut header)
+        .context("Failed to read the list of collected files")?;
+    let (mut files, truncated): (Vec<CollectedFile>, bool) =
+        serde_json::from_slice(&header).context("Invalid list of collected files")?;
+
+    for file in &mut files {
+        let mut contents = Read::by_ref(input).take(file.size);
+        let received = match &config.collect_directory {
+            Some(directory) => {
+                let target = directory.join(file.path.strip_prefix("/")?);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("Failed to create {}", parent.display()))?;
+                }
+                let mut target = File::create(&target)
+                    .with_context(|| format!("Failed to create {}", target.display()))?;
+                io::copy(&mut contents, &mut target)
+            }
+            None => {
+                let mut data = Vec::with_capacity(file.size as usize);
+                let received = contents.read_to_end(&mut data);
+                file.contents = Some(data);
+                received.map(|size| size as u64)
+            }
+        };
+        let received =
+            received.with_context(|| format!("Failed to receive {}", file.path.display()))?;
+        if received != file.size {
+            bail!("Truncated contents of {}", file.path.display());
+        }
+    }
+    Ok((files, truncated))
+}
+
+/// Find the files to collect, from inside the sandbox
+fn find_files(config: &SandboxConfiguration) -> Result<(Vec<CollectedFile>, bool)> {
+    let mut areas: Vec<&Path> = config
+        .tmpfs_mounts
+        .iter()
+        .map(|tmpfs| tmpfs.target.as_path())
+        .collect();
+    if config.mount_tmpfs {
+        areas.extend([Path::new("/tmp"), Path::new("/dev/shm")]);
+    }
+
+    let mut paths = BTreeSet::new();
+    for pattern in &config.collect_files {
+        let components: Vec<&[u8]> = Path::new(pattern)
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(component) => Some(component.as_bytes()),
+                _ => None,
+            })
+            .collect();
+        let mut matches = vec![];
+        glob(Path::new("/"), &components, &areas, &mut matches);
+        paths.extend(
+            matches
+                .into_iter()
+                .filter(|path| areas.iter().any(|area| path.starts_with(area))),
+        );
+    }
+
+    let mut files = vec![];
+    let mut total_size = 0;
+    let mut truncated = false;
+    for path in paths {
+        let size = fs::symlink_metadata(&path)?.len();
+        if let Some(limit) = config.collect_size_limit {
+            if total_size + size > limit {
+                warn!("Not collecting {}: size limit exceeded", path.display());
+                truncated = true;
+                continue;
+            }
+        }
+        total_size += size;
+        files.push(CollectedFile {
+            path,
+            size,
+            contents: None,
+        });
+    }
+    Ok((files, truncated))
+}
+
+/// Find the regular files below `base` matching the components of a glob pattern. The
+/// components support `*`, `?` and `**` (any number of directories). Symbolic links are never
+/// followed, and only the directories inside the `areas` or leading to one of them are visited.
+fn glob(base: &Path, components: &[&[u8]], areas: &[&Path], matches: &mut Vec<PathBuf>) {
+    if !areas
+        .iter()
+        .any(|area| base.starts_with(area) || area.starts_with(base))
+    {
+        return;
+    }
+    let metadata = match fs::symlink_metadata(base) {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+    let (component, rest) = match components.split_first() {
+        Some(split) => split,
+        None => {
+            if metadata.is_file() {
+                matches.push(base.to_owned());
+            }
+            return;
+        }
+    };
+    if *component == b"**" {
+        // Match zero directories, or one more keeping the `**` for the following ones
+        glob(base, rest, areas, matches);
+        if metadata.is_dir() {
+            for entry in fs::read_dir(base).into_iter().flatten().flatten() {
+                glob(&entry.path(), components, areas, matches);
+            }
+        }
+        return;
+    }
+    if !metadata.is_dir() {
+        return;
+    }
+
+    if !component.contains(&b'*') && !component.contains(&b'?') {
+        glob(
+            &base.join(OsStr::from_bytes(component)),
+            rest,
+            areas,
+            matches,
+        );
+        return;
+    }
+    for entry in fs::read_dir(base).into_iter().flatten().flatten() {
+        if wildcard_match(component, entry.file_name().as_bytes()) {
+            glob(&entry.path(), rest, areas, matches);
+        }
+    }
+}
@@ -3,71 +3,92 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 // SPDX-License-Identifier: MPL-2.0
 
-use std::fs;
-use std::path::Path;
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
 
-use anyhow::Context;
-use nix::mount::{mount, MsFlags};
+use anyhow::{bail, Context};
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
 use nix::sys::stat::{mknod, Mode, SFlag};
+use nix::sys::statvfs::{statvfs, FsFlags};
 
-use crate::configuration::{DirectoryMount, SandboxConfiguration};
+use super::{overlay, sandbox_hostname, tar};
+use crate::configuration::{
+    DirectoryMount, InlineFile, MountOptions, OverlayMount, ProcOptions, RootFilesystem,
+    SandboxConfiguration, SocketMount, SysfsMount, TmpfsMount,
+};
 use crate::Result;
 
 /// Create the sandbox filesystem
 pub fn create(config: &SandboxConfiguration, sandbox_path: &Path) -> Result<()> {
+    // Make sure that none of the following mounts propagates back to the host
+    mount(
+        None as Option<&str>,
+        "/",
+        None as Option<&str>,
+        MsFlags::MS_PRIVATE | MsFlags::MS_REC,
+        None as Option<&str>,
+    )
+    .context("Failed to make the mount propagation private")?;
+
     // Create the sandbox dir and mount a tmpfs in it
     mount(
         Some("tmpfs"),
         sandbox_path,
         Some("tmpfs"),
         MsFlags::empty(),
-        Some("size=256M,mode=0755"),
+        Some(format!("size={},mode=0755", config.root_tmpfs_size).as_str()),
     )
     .context("Failed to mount tmpfs for the sandbox")?;
 
+    match &config.rootfs {
+        Some(RootFilesystem::Directory(dir)) => mount_rootfs(dir, sandbox_path)
+            .with_context(|| format!("Failed to mount {} as the root", dir.display()))?,
+        Some(RootFilesystem::Tarball(archive)) => tar::extract(archive, sandbox_path)
+            .with_context(|| format!("Failed to extract {} in the root", archive.display()))?,
+        None => {}
+    }
+
     // Create /dev
-    let dev = sandbox_path.join("dev");
+    let dev = resolve_in_root(sandbox_path, Path::new("/dev"))?;
     fs::create_dir_all(&dev).context("Failed to create /dev for the sandbox")?;
 
-    for device in &["null", "zero", "random", "urandom"] {
-        mount_dev(&dev.join(device), device)
+    for device in &config.devices {
+        mount_dev(device, sandbox_path)
             .with_context(|| format!("Failed to mount /dev/{} in the sandbox", device))?;
     }
 
+    // Standard links to the file descriptors of the process, resolved through /proc
+    if config.mount_proc {
+        for (name, target) in [
+            ("fd", "/proc/self/fd"),
+            ("stdin", "/proc/self/fd/0"),
+            ("stdout", "/proc/self/fd/1"),
+            ("stderr", "/proc/self/fd/2"),
+        ] {
+            let path = dev.join(name);
+            if fs::symlink_metadata(&path).is_err() {
+                std::os::unix::fs::symlink(target, &path)
+                    .with_context(|| format!("Failed to create /dev/{}", name))?;
+            }
+        }
+    }
+
     // Mount /tmp and /dev/shm
     if config.mount_tmpfs {
-        for path in &["tmp", "dev/shm"] {
-            let target_path = sandbox_path.join(path);
-            fs::create_dir_all(&target_path)
-                .with_context(|| format!("Failed to create /{} in the sandbox", path))?;
-            mount(
-                Some("tmpfs"),
-                &target_path,
-                Some("tmpfs"),
-                MsFlags::empty(),
-                Some("size=256M"),
-            )
-            .with_context(|| {
-                format!(
-                    "Failed to mount tmpfs for /{} at {}",
-                    path,
-                    target_path.display()
-                )
-            })?;
+        for path in &["/tmp", "/dev/shm"] {
+            mount_tmpfs(&TmpfsMount::new(*path, 256 * 1024 * 1024), sandbox_path)?;
         }
     }
 
     if config.mount_proc {
-        let target = sandbox_path.join("proc");
-        fs::create_dir_all(&target).context("Failed to create /proc in the sandbox")?;
-        mount(
-            Some("proc"),
-            &target,
-            Some("proc"),
-            MsFlags::empty(),
-            None::<&str>,
-        )
-        .with_context(|| format!("Failed to mount proc at {}", target.display()))?;
+        mount_proc(&config.proc_options, sandbox_path)?;
+    }
+
+    if let Some(sysfs) = config.mount_sys {
+        mount_sys(sysfs, sandbox_path).context("Failed to mount /sys")?;
     }
 
     // bind mount the readable directories into the sandbox
@@ -81,6 +102,33 @@ pub fn create(config: &SandboxConfiguration, sandbox_path: &Path) -> Result<()>
         })?;
     }
 
+    for socket in &config.socket_mounts {
+        mount_socket(socket, sandbox_path).with_context(|| {
+            format!(
+                "Failed to mount socket {} -> {}",
+                socket.source.display(),
+                socket.target.display()
+            )
+        })?;
+    }
+
+    for (index, overlay) in config.overlay_mounts.iter().enumerate() {
+        mount_overlay(overlay, index, sandbox_path)?;
+    }
+
+    for tmpfs in &config.tmpfs_mounts {
+        mount_tmpfs(tmpfs, sandbox_path)?;
+    }
+
+    if config.generate_etc {
+        generate_etc(config, sandbox_path).context("Failed to generate the files in /etc")?;
+    }
+
+    for file in &config.files {
+        write_file(file, sandbox_path)
+            .with_context(|| format!("Failed to create {}", file.target.display()))?;
+    }
+
     // Remount tmpfs read only
     mount(
         None as Option<&str>,
@@ -93,10 +141,399 @@ pub fn create(config: &SandboxConfiguration, sandbox_path: &Path) -> Result<()>
     Ok(())
 }
 
-/// Create a device
-fn mount_dev(path: &Path, dev: &str) -> Result<()> {
+/// List the mount points of the current mount namespace below `path`
+pub fn mounts_below(path: &Path) -> Result<Vec<PathBuf>> {
+    let mountinfo =
+        fs::read_to_string("/proc/self/mountinfo").context("Failed to read mountinfo")?;
+    let mut mounts = vec![];
+    for line in mountinfo.lines() {
+        // The 5th field is the mount point, with spaces and backslashes escaped in octal
+        let mount_point = match line.split(' ').nth(4) {
+            Some(mount_point) => PathBuf::from(unescape_octal(mount_point)),
+            None => continue,
+        };
+        if mount_point.starts_with(path) {
+            mounts.push(mount_point);
+        }
+    }
+    Ok(mounts)
+}
+
+/// Replace the `\ooo` sequences with the corresponding character
+fn unescape_octal(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(index) = rest.find('\\') {
+        result += &rest[..index];
+        let code = rest
+            .get(index + 1..index + 4)
+            .and_then(|code| u8::from_str_radix(code, 8).ok());
+        match code {
+            Some(code) => {
+                result.push(code as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                result.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    result + rest
+}
+
+/// Mount an overlay inside the sandbox, with the upper layer outside of the sandbox root
+fn mount_overlay(overlay: &OverlayMount, index: usize, sandbox_dir: &Path) -> Result<()> {
+    trace!("Mount {:?}", overlay);
+    let target = resolve_in_root(sandbox_dir, &overlay.target)?;
+    fs::create_dir_all(&target).with_context(|| {
+        format!(
+            "Failed to create {} in the sandbox",
+            overlay.target.display()
+        )
+    })?;
+
+    let (upper, work) = overlay::layer_dirs(sandbox_dir, index);
+    for path in [&overlay.lower, &upper, &work] {
+        let path = path.to_string_lossy();
+        if path.contains(',') || path.contains(':') {
+            bail!("Unsupported overlay path: {}", path);
+        }
+    }
+    // Inside a user namespace overlayfs can only use the user.* extended attributes
+    let options = format!(
+        "lowerdir={},upperdir={},workdir={},userxattr",
+        overlay.lower.display(),
+        upper.display(),
+        work.display()
+    );
+
+    mount(
+        Some("overlay"),
+        &target,
+        Some("overlay"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some(options.as_str()),
+    )
+    .with_context(|| {
+        format!(
+            "Failed to mount overlay of {} at {}",
+            overlay.lower.display(),
+            target.display()
+        )
+    })
+}
+
+/// Mount a tmpfs inside the sandbox
+fn mount_tmpfs(tmpfs: &TmpfsMount, sandbox_dir: &Path) -> Result<()> {
+    trace!("Mount {:?}", tmpfs);
+    let target = resolve_in_root(sandbox_dir, &tmpfs.target)?;
+    fs::create_dir_all(&target)
+        .with_context(|| format!("Failed to create {} in the sandbox", tmpfs.target.display()))?;
+
+    let mut options = format!("size={},mode={:o}", tmpfs.size, tmpfs.mode);
+    if let Some(nr_inodes) = tmpfs.nr_inodes {
+        options += &format!(",nr_inodes={}", nr_inodes);
+    }
+    if let Some(uid) = tmpfs.uid {
+        options += &format!(",uid={}", uid);
+    }
+    if let Some(gid) = tmpfs.gid {
+        options += &format!(",gid={}", gid);
+    }
+
+    mount(
+        Some("tmpfs"),
+        &target,
+        Some("tmpfs"),
+        MsFlags::empty(),
+        Some(options.as_str()),
+    )
+    .with_context(|| {
+        format!(
+            "Failed to mount tmpfs for {} at {}",
+            tmpfs.target.display(),
+            target.display()
+        )
+    })
+}
+
+/// Create a file with the specified content inside the sandbox
+fn write_file(file: &InlineFile, sandbox_dir: &Path) -> Result<()> {
+    trace!("Create file {}", file.target.display());
+    if file.target == Path::new("/") {
+        bail!("Cannot create a file at /");
+    }
+    let target = resolve_in_root(sandbox_dir, &file.target)?;
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(&target, &file.contents)
+        .with_context(|| format!("Failed to write {}", target.display()))?;
+    fs::set_permissions(&target, fs::Permissions::from_mode(file.mode))
+        .with_context(|| format!("Failed to set permissions of {}", target.display()))?;
+    Ok(())
+}
+
+/// Generate the files of /etc describing the sandbox user and host. They are written outside of
+/// the sandbox and bound read-only in place, so they also hide the ones of a mounted /etc.
+fn generate_etc(config: &SandboxConfiguration, sandbox_path: &Path) -> Result<()> {
+    let hostname = sandbox_hostname(config).unwrap_or_default();
+    let mut passwd = String::from("root:x:0:0:root:/:/bin/sh\n");
+    let mut group = String::from("root:x:0:\n");
+    if config.uid != 0 {
+        passwd += &format!("user:x:{}:{}:user:/:/bin/sh\n", config.uid, config.gid);
+    }
+    if config.gid != 0 {
+        group += &format!("user:x:{}:\n", config.gid);
+    }
+    passwd += "nobody:x:65534:65534:nobody:/:/bin/false\n";
+    group += "nogroup:x:65534:\n";
+
+    let files = [
+        ("passwd", passwd),
+        ("group", group),
+        (
+            "hosts",
+            format!("127.0.0.1\tlocalhost {0}\n::1\tlocalhost {0}\n", hostname),
+        ),
+        ("hostname", format!("{}\n", hostname)),
+        (
+            "nsswitch.conf",
+            "passwd: files\ngroup: files\nhosts: files\n".to_string(),
+        ),
+    ];
+
+    let staging = sandbox_path.with_file_name("etc");
+    fs::create_dir_all(&staging)
+        .with_context(|| format!("Failed to create {}", staging.display()))?;
+    for (name, contents) in files {
+        // A read-only /etc, like the one of a rootfs, can't get the files it's missing
+        let target = resolve_in_root(sandbox_path, &Path::new("/etc").join(name))?;
+        if !target.exists() {
+            let created = target
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| File::create(&target));
+            if let Err(err) = created {
+                warn!("Not generating /etc/{}: {}", name, err);
+                continue;
+            }
+        }
+        let source = staging.join(name);
+        fs::write(&source, contents)
+            .with_context(|| format!("Failed to write {}", source.display()))?;
+        let file = DirectoryMount {
+            target: Path::new("/etc").join(name),
+            source,
+            writable: false,
+            options: MountOptions::default(),
+        };
+        mount_dir(&file, sandbox_path).with_context(|| format!("Failed to mount /etc/{}", name))?;
+    }
+    Ok(())
+}
+
+/// Populate the root of the sandbox with the content of `dir`, binding read-only each of its
+/// entries. The directories managed by the sandbox itself are skipped.
+fn mount_rootfs(dir: &Path, sandbox_dir: &Path) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for name in entries {
+        if name == "dev" || name == "proc" {
+            continue;
+        }
+        let source = dir.join(&name);
+        let metadata = fs::symlink_metadata(&source)?;
+        if metadata.file_type().is_symlink() {
+            let target = sandbox_dir.join(&name);
+            std::os::unix::fs::symlink(fs::read_link(&source)?, &target)
+                .with_context(|| format!("Failed to create symlink {}", target.display()))?;
+        } else if metadata.is_dir() || metadata.is_file() {
+            let mount = DirectoryMount {
+                target: Path::new("/").join(&name),
+                source,
+                writable: false,
+                options: MountOptions::default(),
+            };
+            mount_dir(&mount, sandbox_dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Maximum number of symbolic links followed when resolving a path inside the sandbox, like the
+/// limit of the kernel
+const MAX_SYMLINKS: usize = 40;
+
+/// Entries of /proc hidden from the sandbox, since they expose information or controls of the host
+const PROC_MASKED_PATHS: &[&str] = &[
+    "acpi",
+    "asound",
+    "kcore",
+    "keys",
+    "latency_stats",
+    "sched_debug",
+    "scsi",
+    "timer_list",
+    "timer_stats",
+];
+
+/// Entries of /proc made read-only inside the sandbox
+const PROC_READONLY_PATHS: &[&str] = &["bus", "fs", "irq", "sys", "sysrq-trigger"];
+
+/// Mount /proc in the sandbox, hiding the sensitive entries if requested
+fn mount_proc(options: &ProcOptions, sandbox_path: &Path) -> Result<()> {
+    let target = resolve_in_root(sandbox_path, Path::new("/proc"))?;
+    fs::create_dir_all(&target).context("Failed to create /proc in the sandbox")?;
+    let mut data = vec![];
+    if options.hide_pids {
+        data.push("hidepid=2");
+    }
+    if options.only_pids {
+        data.push("subset=pid");
+    }
+    mount(
+        Some("proc"),
+        &target,
+        Some("proc"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        Some(data.join(",").as_str()),
+    )
+    .with_context(|| format!("Failed to mount proc at {}", target.display()))?;
+
+    if !options.mask_sensitive {
+        return Ok(());
+    }
+    // Entries missing in this kernel (or because of `subset=pid`) are skipped
+    for name in PROC_MASKED_PATHS {
+        let path = target.join(name);
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let result = if metadata.is_dir() {
+            mount(
+                Some("tmpfs"),
+                &path,
+                Some("tmpfs"),
+                MsFlags::MS_RDONLY,
+                Some("size=0"),
+            )
+        } else {
+            mount(
+                Some("/dev/null"),
+                &path,
+                None as Option<&str>,
+                MsFlags::MS_BIND,
+                None as Option<&str>,
+            )
+        };
+        result.with_context(|| format!("Failed to mask /proc/{}", name))?;
+    }
+    for name in PROC_READONLY_PATHS {
+        let path = target.join(name);
+        if fs::symlink_metadata(&path).is_err() {
+            continue;
+        }
+        mount(
+            Some(&path),
+            &path,
+            None as Option<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            None as Option<&str>,
+        )
+        .with_context(|| format!("Failed to bind-mount /proc/{}", name))?;
+        mount(
+            None as Option<&str>,
+            &path,
+            None as Option<&str>,
+            MsFlags::MS_REMOUNT | MsFlags::MS_BIND | MsFlags::MS_RDONLY | locked_flags(&path)?,
+            None as Option<&str>,
+        )
+        .with_context(|| format!("Failed to make /proc/{} read-only", name))?;
+    }
+    Ok(())
+}
+
+/// Mount a read-only sysfs in /sys of the sandbox. With `SysfsMount::CpuTopology` the sysfs is
+/// mounted outside of the sandbox, and only the CPU directory is bound into an empty /sys.
+fn mount_sys(sysfs: SysfsMount, sandbox_path: &Path) -> Result<()> {
+    let target = resolve_in_root(sandbox_path, Path::new("/sys"))?;
+    fs::create_dir_all(&target).context("Failed to create /sys in the sandbox")?;
+    let flags = MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+    if sysfs == SysfsMount::Full {
+        return mount(
+            Some("sysfs"),
+            &target,
+            Some("sysfs"),
+            flags,
+            None as Option<&str>,
+        )
+        .context("Failed to mount sysfs");
+    }
+
+    let staging = sandbox_path.with_file_name("sys");
+    fs::create_dir_all(&staging).context("Failed to create the sysfs staging directory")?;
+    mount(
+        Some("sysfs"),
+        &staging,
+        Some("sysfs"),
+        flags,
+        None as Option<&str>,
+    )
+    .context("Failed to mount sysfs")?;
+
+    mount(
+        Some("tmpfs"),
+        &target,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        Some("size=64k,mode=0755"),
+    )
+    .context("Failed to mount tmpfs on /sys")?;
+    let cpu = Path::new("devices/system/cpu");
+    fs::create_dir_all(target.join(cpu)).context("Failed to create /sys/devices/system/cpu")?;
+    mount(
+        Some(&staging.join(cpu)),
+        &target.join(cpu),
+        None as Option<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None as Option<&str>,
+    )
+    .context("Failed to bind-mount /sys/devices/system/cpu")?;
+    mount(
+        None as Option<&str>,
+        &target,
+        None as Option<&str>,
+        MsFlags::MS_REMOUNT | flags,
+        None as Option<&str>,
+    )
+    .context("Failed to remount /sys read-only")?;
+    umount2(&staging, MntFlags::MNT_DETACH).context("Failed to unmount the sysfs staging")?;
+    Ok(())
+}
+
+/// Bind-mount the device `/dev/<name>` of the host into the sandbox
+fn mount_dev(name: &str, sandbox_dir: &Path) -> Result<()> {
+    let relative = Path::new(name);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        bail!("Invalid device name: {}", name);
+    }
+    let path = resolve_in_root(sandbox_dir, &Path::new("/dev").join(relative))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
     mknod(
-        path,
+        &path,
         SFlag::empty(),
         Mode::S_IRUSR
             | Mode::S_IWUSR
@@ -108,31 +545,89 @@ fn mount_dev(path: &Path, dev: &str) -> Result<()> {
     )
     .with_context(|| format!("Failed to mknod {}", path.display()))?;
     mount(
-        Some(&Path::new("/dev").join(dev)),
-        path,
+        Some(&Path::new("/dev").join(relative)),
+        &path,
         None as Option<&str>,
         MsFlags::MS_BIND,
         None as Option<&str>,
     )
-    .with_context(|| format!("Failed to bind-mount /dev/{}", dev))
+    .with_context(|| format!("Failed to bind-mount /dev/{}", name))
+}
+
+/// Path on the host of `path` inside the sandbox. The symbolic links already in the sandbox, like
+/// the ones of the root filesystem image, are resolved as if `sandbox_dir` was the root: neither
+/// absolute links nor `..` can lead outside of it.
+fn resolve_in_root(sandbox_dir: &Path, path: &Path) -> Result<PathBuf> {
+    let mut resolved = sandbox_dir.to_owned();
+    let mut rest: VecDeque<OsString> = path
+        .components()
+        .map(|component| component.as_os_str().to_owned())
+        .collect();
+    let mut links = 0;
+    while let Some(component) = rest.pop_front() {
+        match Path::new(&component).components().next() {
+            Some(Component::Normal(name)) => {
+                let next = resolved.join(name);
+                if !fs::symlink_metadata(&next).is_ok_and(|m| m.file_type().is_symlink()) {
+                    resolved = next;
+                    continue;
+                }
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    bail!("Too many symbolic links resolving {}", path.display());
+                }
+                let link = fs::read_link(&next)
+                    .with_context(|| format!("Failed to read symlink {}", next.display()))?;
+                if link.is_absolute() {
+                    resolved = sandbox_dir.to_owned();
+                }
+                for component in link.components().rev() {
+                    rest.push_front(component.as_os_str().to_owned());
+                }
+            }
+            Some(Component::ParentDir) if resolved != sandbox_dir => {
+                resolved.pop();
+            }
+            _ => {}
+        }
+    }
+    Ok(resolved)
 }
 
-/// Mount a directory inside the sandbox
+/// Mount a directory, or a single file, inside the sandbox
 fn mount_dir(dir: &DirectoryMount, sandbox_dir: &Path) -> Result<()> {
     trace!("Mount {:?}", dir);
     assert_ne!(dir.target, Path::new("/"));
 
     // Join destination with the sandbox directory
-    let target = sandbox_dir.join(dir.target.strip_prefix("/")?);
+    let target = resolve_in_root(sandbox_dir, &dir.target)?;
 
-    fs::create_dir_all(&target)
-        .with_context(|| format!("Failed to create mount target at {}", target.display()))?;
+    if dir.source.is_dir() {
+        fs::create_dir_all(&target)
+            .with_context(|| format!("Failed to create mount target at {}", target.display()))?;
+    } else {
+        // Bind-mounting a file requires an existing file as the target
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create mount target parent {}", parent.display())
+            })?;
+        }
+        if !target.exists() {
+            File::create(&target).with_context(|| {
+                format!("Failed to create mount target at {}", target.display())
+            })?;
+        }
+    }
 
+    let mut flags = MsFlags::MS_BIND;
+    if dir.options.recursive {
+        flags |= MsFlags::MS_REC;
+    }
     mount(
         Some(&dir.source),
         &target,
         None as Option<&str>,
-        MsFlags::MS_BIND | MsFlags::MS_REC,
+        flags,
         None as Option<&str>,
     )
     .with_context(|| {
@@ -143,19 +638,69 @@ fn mount_dir(dir: &DirectoryMount, sandbox_dir: &Path) -> Result<()> {
         )
     })?;
 
+    // The flags of a bind mount can only be changed with a remount. Inside a user namespace the
+    // flags inherited from the source mount are locked, so they have to be kept.
+    let mut flags = MsFlags::MS_REMOUNT | MsFlags::MS_BIND | locked_flags(&target)?;
     if !dir.writable {
-        mount(
-            None as Option<&str>,
-            &target,
-            None as Option<&str>,
-            MsFlags::MS_REMOUNT
-                | MsFlags::MS_RDONLY
-                | MsFlags::MS_BIND
-                | MsFlags::MS_NOSUID
-                | MsFlags::MS_NODEV,
-            None as Option<&str>,
-        )
-        .with_context(|| format!("Failed to readonly remount at {}", target.display()))?;
+        flags |= MsFlags::MS_RDONLY;
+    }
+    if dir.options.noexec {
+        flags |= MsFlags::MS_NOEXEC;
     }
+    if dir.options.nosuid {
+        flags |= MsFlags::MS_NOSUID;
+    }
+    if dir.options.nodev {
+        flags |= MsFlags::MS_NODEV;
+    }
+    mount(
+        None as Option<&str>,
+        &target,
+        None as Option<&str>,
+        flags,
+        None as Option<&str>,
+    )
+    .with_context(|| format!("Failed to remount {} with {:?}", target.display(), flags))?;
     Ok(())
 }
+
+/// Bind a unix socket of the host inside the sandbox. Connecting to a socket is allowed also
+/// through a read-only mount.
+fn mount_socket(socket: &SocketMount, sandbox_dir: &Path) -> Result<()> {
+    let metadata = fs::metadata(&socket.source)
+        .with_context(|| format!("Failed to stat {}", socket.source.display()))?;
+    if !metadata.file_type().is_socket() {
+        bail!("{} is not a unix socket", socket.source.display());
+    }
+    let mount = DirectoryMount {
+        target: socket.target.clone(),
+        source: socket.source.clone(),
+        writable: false,
+        options: MountOptions {
+            noexec: true,
+            recursive: false,
+            ..Default::default()
+        },
+    };
+    mount_dir(&mount, sandbox_dir)
+}
+
+/// Flags of the mount at `path` that cannot be cleared by a remount inside the user namespace
+fn locked_flags(path: &Path) -> Result<MsFlags> {
+    let stat = statvfs(path).with_context(|| format!("Failed to statvfs {}", path.display()))?;
+    let mut flags = MsFlags::empty();
+    for (fs_flag, ms_flag) in [
+        (FsFlags::ST_RDONLY, MsFlags::MS_RDONLY),
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        if stat.flags().contains(fs_flag) {
+            flags |= ms_flag;
+        }
+    }
+    Ok(flags)
+}
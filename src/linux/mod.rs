@@ -5,7 +5,12 @@
 
 //! This module contains the sandbox for Linux
 
-use std::fs::File;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::os::unix::process::CommandExt;
 use std::path::Path;
 use std::process::Command;
@@ -16,16 +21,22 @@ use std::thread::{self, JoinHandle};
 use std::time::Instant;
 
 use anyhow::{anyhow, bail, Context};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::mount::{umount2, MntFlags};
 use nix::sys::signal::{kill, Signal};
 use nix::unistd::{self, Gid, Pid, Uid};
 
-use crate::configuration::SandboxConfiguration;
-use crate::result::{ExitStatus, ResourceUsage, SandboxExecutionResult};
+use crate::configuration::{ClockOffsets, Network, SandboxConfiguration};
+use crate::result::{DeterministicSettings, ExitStatus, ResourceUsage, SandboxExecutionResult};
 use crate::util::{setup_resource_limits, start_wall_time_watcher, strerror, wait};
 use crate::{Result, Sandbox};
 
+mod collect;
 mod filesystem;
+mod overlay;
 mod seccomp_filter;
+mod tar;
 
 lazy_static! {
     /// PID of the child process, will be used to kill the child when SIGTERM or SIGINT is received.
@@ -84,9 +95,18 @@ impl Sandbox for LinuxSandbox {
         true
     }
 }
-fn watcher(config: SandboxConfiguration) -> Result<SandboxExecutionResult> {
-    let tempdir = tempfile::TempDir::new().context("Failed to create sandbox tempdir")?;
-    let sandbox_path = tempdir.path();
+fn watcher(mut config: SandboxConfiguration) -> Result<SandboxExecutionResult> {
+    // In deterministic mode the sandbox always runs on a single core
+    if config.deterministic && config.cpu_core.is_none() {
+        config.cpu_core = Some(first_allowed_core()?);
+    }
+
+    let tempdir = tempfile::Builder::new()
+        .prefix("tabox-")
+        .tempdir()
+        .context("Failed to create sandbox tempdir")?;
+    let sandbox_path = &tempdir.path().join("root");
+    std::fs::create_dir(sandbox_path).context("Failed to create sandbox root")?;
 
     // uid/gid from outside the sandbox
     let uid = unistd::getuid();
@@ -105,24 +125,51 @@ fn watcher(config: SandboxConfiguration) -> Result<SandboxExecutionResult> {
         Error(usize, [char; 1024]),
     }
 
+    struct SharedState {
+        error: ErrorMessage,
+        /// Whether the child used `chroot` instead of `pivot_root` for entering the sandbox
+        chroot_fallback: bool,
+    }
+
     // Allocate some memory that the forked process can use to write the error. This memory is
-    // page-aligned, which is hopefully enough for ErrorMessage.
+    // page-aligned, which is hopefully enough for SharedState.
     let shared = unsafe {
-        std::mem::transmute(libc::mmap(
+        libc::mmap(
             std::ptr::null_mut(),
-            std::mem::size_of::<ErrorMessage>(),
+            std::mem::size_of::<SharedState>(),
             libc::PROT_READ | libc::PROT_WRITE,
             libc::MAP_ANONYMOUS | libc::MAP_SHARED,
             0,
             0,
-        ))
+        )
+        .cast::<SharedState>()
     };
     // Cleanup the shared memory: by default there is no error (we cannot set it after because the
     // child process execs and this memory will be unreachable).
-    unsafe { std::ptr::write(shared, ErrorMessage::NoError) };
+    unsafe {
+        std::ptr::write(
+            shared,
+            SharedState {
+                error: ErrorMessage::NoError,
+                chroot_fallback: false,
+            },
+        )
+    };
+
+    // The child waits for the watcher to be ready before starting the setup of the sandbox
+    let (ready_output, ready_input) =
+        unistd::pipe2(OFlag::O_CLOEXEC).context("Failed to create pipe")?;
 
     let child_pid = spawn_child(|| {
-        if let Err(err) = child(&config, sandbox_path, uid, gid) {
+        let _ = unistd::close(ready_input.as_raw_fd());
+        let mut buffer = [0u8; 1];
+        if !matches!(unistd::read(ready_output.as_raw_fd(), &mut buffer), Ok(1)) {
+            error!("The watcher is not ready, aborting");
+            return;
+        }
+
+        let chroot_fallback = unsafe { &mut (*shared).chroot_fallback };
+        if let Err(err) = child(&config, sandbox_path, chroot_fallback) {
             error!("Child failed: {:?}", err);
 
             // prepare a buffer where to write the error message
@@ -134,7 +181,7 @@ fn watcher(config: SandboxConfiguration) -> Result<SandboxExecutionResult> {
             // Write the error message to the shared memory. This is safe since the parent will not
             // read from it until this process has completely exited.
             let error = ErrorMessage::Error(message.len(), buffer);
-            unsafe { std::ptr::write(shared, error) };
+            unsafe { std::ptr::write(&mut (*shared).error, error) };
         } else {
             unreachable!("The child process must exec");
         }
@@ -143,6 +190,33 @@ fn watcher(config: SandboxConfiguration) -> Result<SandboxExecutionResult> {
 
     // Store the PID of the child process for letting the signal handler kill the child
     CHILD_PID.store(child_pid, Ordering::SeqCst);
+    let mut child_guard = ChildGuard {
+        pid: child_pid,
+        reaped: false,
+    };
+
+    setup_id_maps(child_pid, &config, uid, gid).context("Failed to map the sandbox user")?;
+
+    // Keep the namespaces of the child: the overlay layers are created in them before the setup
+    // of the sandbox, and the files are collected after it has exited
+    let namespaces = if config.collect_files.is_empty() && config.overlay_mounts.is_empty() {
+        None
+    } else {
+        Some(
+            collect::SandboxNamespaces::open(child_pid)
+                .context("Failed to open sandbox namespaces")?,
+        )
+    };
+    let layers = match &namespaces {
+        Some(namespaces) => {
+            overlay::create_layers(&config.overlay_mounts, sandbox_path, namespaces)
+                .context("Failed to create overlay layers")?
+        }
+        None => vec![],
+    };
+    drop(ready_output);
+    unistd::write(&ready_input, &[1]).context("Failed to start the child")?;
+    drop(ready_input);
 
     let start_time = Instant::now();
 
@@ -155,14 +229,38 @@ fn watcher(config: SandboxConfiguration) -> Result<SandboxExecutionResult> {
 
     // Wait child for completion
     let (status, resource_usage) = wait(child_pid).context("Failed to wait for child process")?;
+    child_guard.reaped = true;
+
+    let wall_time_usage = (Instant::now() - start_time).as_secs_f64();
+
+    // The sandbox mounts live only in the namespace of the child, check that none of them
+    // appeared in the one of the host, also when the setup of the sandbox failed halfway.
+    let leaked = filesystem::mounts_below(tempdir.path())?;
+    if !leaked.is_empty() {
+        bail!("Sandbox mounts leaked into the host: {:?}", leaked);
+    }
 
     // Read from shared memory if there was an error with the sandbox. At this point the child
     // process has for sure exited, so it's safe to read.
-    if let ErrorMessage::Error(len, error) = unsafe { std::ptr::read(shared) } {
+    let state = unsafe { std::ptr::read(shared) };
+    if let ErrorMessage::Error(len, error) = state.error {
         let message = error.iter().take(len).collect::<String>();
         bail!("{}", message);
     }
 
+    let file_changes = overlay::collect_changes(&config.overlay_mounts, &layers)
+        .context("Failed to collect the changes to the overlays")?;
+
+    let (collected_files, collected_files_truncated) = match &namespaces {
+        Some(namespaces) => {
+            // After pivot_root the root of the mount namespace is already the sandbox
+            let root = state.chroot_fallback.then_some(sandbox_path.as_path());
+            collect::collect_files(&config, namespaces, root)
+                .context("Failed to collect the output files")?
+        }
+        None => (vec![], false),
+    };
+
     Ok(SandboxExecutionResult {
         status: if killed.load(Ordering::SeqCst) {
             ExitStatus::Killed
@@ -170,12 +268,41 @@ fn watcher(config: SandboxConfiguration) -> Result<SandboxExecutionResult> {
             status
         },
         resource_usage: ResourceUsage {
-            wall_time_usage: (Instant::now() - start_time).as_secs_f64(),
+            wall_time_usage,
             ..resource_usage
         },
+        file_changes,
+        collected_files,
+        collected_files_truncated,
+        deterministic: config.deterministic.then(|| DeterministicSettings {
+            aslr_disabled: true,
+            hostname: sandbox_hostname(&config).unwrap_or_default().to_string(),
+            cpu_core: config.cpu_core.unwrap_or_default(),
+        }),
     })
 }
 
+/// Kills and reaps the child process if the watcher fails before waiting for it, so that no
+/// zombie is left behind and the signal handler does not target a stale PID.
+struct ChildGuard {
+    pid: libc::pid_t,
+    reaped: bool,
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        if !self.reaped {
+            if let Err(e) = kill(Pid::from_raw(self.pid), Signal::SIGKILL) {
+                error!("Cannot kill {}: {:?}", self.pid, e);
+            }
+            if unsafe { libc::waitpid(self.pid, std::ptr::null_mut(), 0) } != self.pid {
+                error!("Cannot reap {}: {}", self.pid, strerror());
+            }
+        }
+        let _ = CHILD_PID.compare_exchange(self.pid, -1, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
 /// Spawn the child process inside of an unshared environment.
 ///
 /// This makes sure the child process exits when it's done.
@@ -189,6 +316,7 @@ fn spawn_child(child: impl FnOnce()) -> Result<libc::pid_t> {
                 | libc::CLONE_NEWPID
                 | libc::CLONE_NEWUSER
                 | libc::CLONE_NEWUTS
+                | libc::CLONE_NEWCGROUP
                 | libc::SIGCHLD,
             null::<libc::c_void>(),
         )
@@ -208,22 +336,28 @@ fn spawn_child(child: impl FnOnce()) -> Result<libc::pid_t> {
     Ok(child_pid)
 }
 
-/// Child process
-fn child(config: &SandboxConfiguration, sandbox_path: &Path, uid: Uid, gid: Gid) -> Result<()> {
-    // Map current uid/gid to root/root inside the sandbox
-    std::fs::write("/proc/self/setgroups", "deny")
-        .context("Failed to write /proc/self/setgroups")?;
-    std::fs::write(
-        "/proc/self/uid_map",
-        format!("{} {} 1", config.uid, uid.as_raw()),
-    )
-    .context("Failed to write /proc/self/uid_map")?;
-    std::fs::write(
-        "/proc/self/gid_map",
-        format!("{} {} 1", config.gid, gid.as_raw()),
-    )
-    .context("Failed to write /proc/self/gid_map")?;
+/// Map the uid/gid of the watcher to the ones of the sandbox, in the user namespace of the child
+fn setup_id_maps(
+    child_pid: libc::pid_t,
+    config: &SandboxConfiguration,
+    uid: Uid,
+    gid: Gid,
+) -> Result<()> {
+    let write = |name: &str, content: String| {
+        let path = format!("/proc/{}/{}", child_pid, name);
+        std::fs::write(&path, content).with_context(|| format!("Failed to write {}", path))
+    };
+    write("setgroups", "deny".to_string())?;
+    write("uid_map", format!("{} {} 1", config.uid, uid.as_raw()))?;
+    write("gid_map", format!("{} {} 1", config.gid, gid.as_raw()))
+}
 
+/// Child process
+fn child(
+    config: &SandboxConfiguration,
+    sandbox_path: &Path,
+    chroot_fallback: &mut bool,
+) -> Result<()> {
     // When parent dies, I want to die too
     if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) < 0 } {
         bail!("Error calling prctl(): {}", strerror());
@@ -231,6 +365,24 @@ fn child(config: &SandboxConfiguration, sandbox_path: &Path, uid: Uid, gid: Gid)
 
     assert_eq!(unistd::getpid().as_raw(), 1);
 
+    setup_file_descriptors(config).context("Failed to setup file descriptors")?;
+
+    if let Some(hostname) = sandbox_hostname(config) {
+        unistd::sethostname(hostname).context("Failed to set the hostname")?;
+    }
+
+    if config.deterministic {
+        disable_aslr().context("Failed to disable ASLR")?;
+    }
+
+    if let Some(offsets) = &config.time_namespace {
+        setup_time_namespace(offsets).context("Failed to setup the time namespace")?;
+    }
+
+    if config.network == Network::Loopback {
+        setup_loopback().context("Failed to setup the loopback interface")?;
+    }
+
     let mut command = Command::new(&config.executable);
 
     command
@@ -256,9 +408,13 @@ fn child(config: &SandboxConfiguration, sandbox_path: &Path, uid: Uid, gid: Gid)
         command.stderr(stderr);
     }
 
+    if config.exec_from_host {
+        exec_from_host(config, &mut command)?;
+    }
+
     filesystem::create(config, sandbox_path).context("Failed to create sandbox filesystem")?;
     setup_thread_affinity(config).context("Failed to setup thread affinity")?;
-    enter_chroot(config, sandbox_path).context("Failed to enter chroot")?;
+    *chroot_fallback = enter_chroot(config, sandbox_path).context("Failed to enter chroot")?;
     setup_resource_limits(config).context("Failed to setup rlimits")?;
     setup_syscall_filter(config).context("Failed to setup syscall filter")?;
 
@@ -266,6 +422,214 @@ fn child(config: &SandboxConfiguration, sandbox_path: &Path, uid: Uid, gid: Gid)
     Err(command.exec()).context("Failed to exec child process")
 }
 
+/// Open the executable from the host and make `command` execute it with `execveat`, instead of
+/// looking it up inside the sandbox.
+fn exec_from_host(config: &SandboxConfiguration, command: &mut Command) -> Result<()> {
+    // The interpreter of a script would get the path of the close-on-exec file descriptor,
+    // which no longer exists, and execveat would fail with ENOENT. A program that can't be read
+    // can't be a script, since the interpreter couldn't read it either.
+    if let Ok(mut file) = File::open(&config.executable) {
+        let mut magic = [0u8; 4];
+        if file.read_exact(&mut magic).is_err() || &magic != b"\x7fELF" {
+            bail!(
+                "{} is not an ELF executable, only those can be executed from the host",
+                config.executable.display()
+            );
+        }
+    }
+
+    // O_PATH is enough for executing, the program may not be readable
+    let executable = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH)
+        .open(&config.executable)
+        .with_context(|| format!("Failed to open {}", config.executable.display()))?;
+
+    let to_cstring = |s: &[u8]| CString::new(s).context("Argument with a NUL byte");
+    let args = std::iter::once(to_cstring(config.executable.as_os_str().as_bytes()))
+        .chain(config.args.iter().map(|arg| to_cstring(arg.as_bytes())))
+        .collect::<Result<Vec<_>>>()?;
+    let env = config
+        .env
+        .iter()
+        .map(|(name, value)| to_cstring(format!("{}={}", name, value).as_bytes()))
+        .collect::<Result<Vec<_>>>()?;
+
+    // The hook runs after the standard file descriptors have been redirected, right before the
+    // exec of the command, which is never reached if execveat succeeds.
+    unsafe {
+        command.pre_exec(move || {
+            let mut argv: Vec<_> = args.iter().map(|arg| arg.as_ptr()).collect();
+            argv.push(null());
+            let mut envp: Vec<_> = env.iter().map(|var| var.as_ptr()).collect();
+            envp.push(null());
+            libc::syscall(
+                libc::SYS_execveat,
+                executable.as_raw_fd(),
+                c"".as_ptr(),
+                argv.as_ptr(),
+                envp.as_ptr(),
+                libc::AT_EMPTY_PATH,
+            );
+            Err(std::io::Error::last_os_error())
+        });
+    }
+    Ok(())
+}
+
+/// Disable the address space layout randomization for the program, the setting is kept across exec
+fn disable_aslr() -> Result<()> {
+    // Passing an invalid persona only queries the current one
+    let persona = unsafe { libc::personality(0xffffffff) };
+    if persona < 0 {
+        bail!("personality() error: {}", strerror());
+    }
+    if unsafe { libc::personality((persona | libc::ADDR_NO_RANDOMIZE) as libc::c_ulong) } < 0 {
+        bail!("personality() error: {}", strerror());
+    }
+    Ok(())
+}
+
+/// First CPU core the current process is allowed to run on
+fn first_allowed_core() -> Result<usize> {
+    let cpu_set = nix::sched::sched_getaffinity(Pid::from_raw(0))
+        .context("Failed to get the CPU affinity")?;
+    (0..nix::sched::CpuSet::count())
+        .find(|core| cpu_set.is_set(*core).unwrap_or(false))
+        .context("No CPU core available")
+}
+
+/// Create the time namespace where the program is executed, with the specified clock offsets. The
+/// process itself enters the namespace only when it execs.
+fn setup_time_namespace(offsets: &ClockOffsets) -> Result<()> {
+    if unsafe { libc::unshare(libc::CLONE_NEWTIME) } < 0 {
+        bail!("unshare(CLONE_NEWTIME) error: {}", strerror());
+    }
+    std::fs::write(
+        "/proc/self/timens_offsets",
+        format!(
+            "monotonic {} 0\nboottime {} 0\n",
+            offsets.monotonic, offsets.boottime
+        ),
+    )
+    .context("Failed to write /proc/self/timens_offsets")?;
+    Ok(())
+}
+
+/// Bring up the loopback interface of the network namespace of the sandbox
+fn setup_loopback() -> Result<()> {
+    let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if socket < 0 {
+        bail!("socket() error: {}", strerror());
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(socket) };
+
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, src) in request.ifr_name.iter_mut().zip(b"lo") {
+        *dst = *src as libc::c_char;
+    }
+    if unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCGIFFLAGS, &mut request) } < 0 {
+        bail!("Failed to get the flags of lo: {}", strerror());
+    }
+    unsafe { request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short };
+    if unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCSIFFLAGS, &request) } < 0 {
+        bail!("Failed to bring lo up: {}", strerror());
+    }
+    Ok(())
+}
+
+/// Hostname to set inside the sandbox, if it has to be different from the one of the host
+fn sandbox_hostname(config: &SandboxConfiguration) -> Option<&str> {
+    match &config.hostname {
+        Some(hostname) => Some(hostname),
+        // The generated /etc/hostname must not leak the name of the host
+        None if config.generate_etc || config.deterministic => Some("sandbox"),
+        None => None,
+    }
+}
+
+/// Move the extra file descriptors to their number inside the sandbox, and close every other
+/// descriptor apart from stdin/stdout/stderr.
+fn setup_file_descriptors(config: &SandboxConfiguration) -> Result<()> {
+    let targets: Vec<RawFd> = config.extra_fds.iter().map(|(_, target)| *target).collect();
+    for (i, target) in targets.iter().enumerate() {
+        if *target <= libc::STDERR_FILENO {
+            bail!(
+                "Cannot pass a file descriptor as {}: it's reserved for stdio",
+                target
+            );
+        }
+        if targets[..i].contains(target) {
+            bail!("File descriptor {} is passed more than once", target);
+        }
+    }
+
+    // Duplicate all the sources above the target numbers first, otherwise moving a descriptor
+    // may overwrite the source of another one.
+    let lowest = targets.iter().max().map_or(0, |max| max + 1);
+    let mut sources = Vec::with_capacity(targets.len());
+    for (source, _) in &config.extra_fds {
+        let fd = fcntl(*source, FcntlArg::F_DUPFD_CLOEXEC(lowest))
+            .with_context(|| format!("Failed to duplicate file descriptor {}", source))?;
+        sources.push(fd);
+    }
+    for (source, target) in sources.into_iter().zip(&targets) {
+        unistd::dup2(source, *target)
+            .with_context(|| format!("Failed to move file descriptor to {}", target))?;
+    }
+
+    // Close everything else, including the descriptors inherited from the embedding application
+    // that were not opened with O_CLOEXEC.
+    let mut targets = targets;
+    targets.sort_unstable();
+    let mut first = libc::STDERR_FILENO + 1;
+    for target in targets {
+        close_range(first, target - 1)?;
+        first = target + 1;
+    }
+    close_range(first, RawFd::MAX)
+}
+
+/// Close all the file descriptors in the range [first, last]
+fn close_range(first: RawFd, last: RawFd) -> Result<()> {
+    if first > last {
+        return Ok(());
+    }
+    if unsafe { libc::syscall(libc::SYS_close_range, first as u32, last as u32, 0) } < 0 {
+        // close_range is only available since Linux 5.9
+        if Errno::last() == Errno::ENOSYS {
+            return set_cloexec_range(first, last);
+        }
+        bail!(
+            "Error calling close_range({}, {}): {}",
+            first,
+            last,
+            strerror()
+        );
+    }
+    Ok(())
+}
+
+/// Mark all the open file descriptors in the range [first, last] as close-on-exec, listing them
+/// from /proc/self/fd.
+fn set_cloexec_range(first: RawFd, last: RawFd) -> Result<()> {
+    let fds: Vec<RawFd> = std::fs::read_dir("/proc/self/fd")
+        .context("Failed to list /proc/self/fd")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    for fd in fds {
+        if fd < first || fd > last {
+            continue;
+        }
+        // the descriptor used for listing the directory is in the list, but it's now closed
+        match fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)) {
+            Ok(_) | Err(Errno::EBADF) => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to set FD_CLOEXEC on {}", fd)),
+        }
+    }
+    Ok(())
+}
+
 /// Set cpu affinity
 fn setup_thread_affinity(config: &SandboxConfiguration) -> Result<()> {
     if let Some(core) = config.cpu_core {
@@ -277,13 +641,20 @@ fn setup_thread_affinity(config: &SandboxConfiguration) -> Result<()> {
     Ok(())
 }
 
-/// Enter the sandbox chroot and change directory
-fn enter_chroot(config: &SandboxConfiguration, sandbox_path: &Path) -> Result<()> {
-    // Chroot into the sandbox
-    unistd::chroot(sandbox_path).context("Failed to chroot")?;
+/// Make the sandbox the root directory of the process. Returns whether `chroot` had to be used
+/// instead of `pivot_root`.
+fn enter_chroot(config: &SandboxConfiguration, sandbox_path: &Path) -> Result<bool> {
+    let chroot_fallback = match pivot_root(sandbox_path) {
+        Ok(()) => false,
+        Err(err) => {
+            warn!("pivot_root failed, falling back to chroot: {:?}", err);
+            unistd::chroot(sandbox_path).context("Failed to chroot")?;
+            true
+        }
+    };
 
     // Check that things exits inside
-    if !config.executable.exists() {
+    if !config.exec_from_host && !config.executable.exists() {
         bail!("Executable doesn't exist inside the sandbox chroot. Perhaps you need to mount some directories?");
     }
     if !config.working_directory.exists() {
@@ -292,6 +663,17 @@ fn enter_chroot(config: &SandboxConfiguration, sandbox_path: &Path) -> Result<()
 
     // Change to working directory
     unistd::chdir(&config.working_directory).context("Failed to chdir")?;
+    Ok(chroot_fallback)
+}
+
+/// Switch the root of the mount namespace to the sandbox, detaching the old root: differently
+/// from chroot, nothing of the host filesystem remains reachable.
+fn pivot_root(sandbox_path: &Path) -> Result<()> {
+    unistd::chdir(sandbox_path).context("Failed to chdir into the sandbox")?;
+    // The old root is stacked on top of the new one, so no directory is needed for it
+    unistd::pivot_root(".", ".").context("Failed to pivot_root")?;
+    umount2(".", MntFlags::MNT_DETACH).context("Failed to detach the old root")?;
+    unistd::chdir("/").context("Failed to chdir")?;
     Ok(())
 }
 
@@ -305,6 +687,11 @@ fn setup_syscall_filter(config: &SandboxConfiguration) -> Result<()> {
                 format!("Failed to add syscall filter: {} {:?}", syscall, action)
             })?;
         }
+        for rule in &syscall_filter.conditional_rules {
+            filter
+                .filter_conditional(&rule.syscall, rule.action, &rule.conditions)
+                .with_context(|| format!("Failed to add syscall filter: {:?}", rule))?;
+        }
         filter.load().context("Failed to load syscall filter")?;
     }
     Ok(())
@@ -0,0 +1,200 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use nix::mount::{mount, MsFlags};
+
+use super::collect::{receive_fd, send_fd, SandboxNamespaces};
+use crate::configuration::OverlayMount;
+use crate::result::FileChange;
+use crate::Result;
+
+/// Extended attribute that overlayfs sets on the directories that hide the lower layer
+const OPAQUE_XATTR: &str = "user.overlay.opaque";
+
+/// Directories of the upper layer and of the work directory of the overlay with the specified
+/// index. They are on a tmpfs mounted next to the sandbox root, outside of it, in the mount
+/// namespace of the sandbox.
+pub fn layer_dirs(sandbox_path: &Path, index: usize) -> (PathBuf, PathBuf) {
+    let base = layers_mount_point(sandbox_path, index);
+    (base.join("upper"), base.join("work"))
+}
+
+/// Where the tmpfs with the layers of the overlay with the specified index is mounted
+fn layers_mount_point(sandbox_path: &Path, index: usize) -> PathBuf {
+    sandbox_path.with_file_name(format!("overlay-{}", index))
+}
+
+/// Mount the tmpfs with the layers of each overlay in the namespaces of the sandbox, before its
+/// setup starts. Since the sandbox moves away from the original root, the returned directories
+/// are the only way for reaching the layers after the execution.
+pub fn create_layers(
+    overlays: &[OverlayMount],
+    sandbox_path: &Path,
+    namespaces: &SandboxNamespaces,
+) -> Result<Vec<File>> {
+    for index in 0..overlays.len() {
+        let dir = layers_mount_point(sandbox_path, index);
+        fs::create_dir(&dir)
+            .with_context(|| format!("Failed to create overlay layers {}", dir.display()))?;
+    }
+    namespaces.run(
+        None,
+        |socket| {
+            for (index, overlay) in overlays.iter().enumerate() {
+                let dir = layers_mount_point(sandbox_path, index);
+                mount(
+                    Some("tmpfs"),
+                    &dir,
+                    Some("tmpfs"),
+                    MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                    Some(format!("size={},mode=0700", overlay.size).as_str()),
+                )
+                .with_context(|| format!("Failed to mount tmpfs at {}", dir.display()))?;
+                let (upper, work) = layer_dirs(sandbox_path, index);
+                for dir in [upper, work] {
+                    fs::create_dir(&dir).with_context(|| {
+                        format!("Failed to create overlay layer {}", dir.display())
+                    })?;
+                }
+                let dir = File::open(&dir)
+                    .with_context(|| format!("Failed to open {}", dir.display()))?;
+                send_fd(socket, dir.as_raw_fd())?;
+            }
+            Ok(())
+        },
+        |socket| {
+            overlays
+                .iter()
+                .map(|_| Ok(File::from(receive_fd(socket)?)))
+                .collect()
+        },
+    )
+}
+
+/// Compute the changes made by the program to the overlays, copying them out if requested
+pub fn collect_changes(overlays: &[OverlayMount], layers: &[File]) -> Result<Vec<FileChange>> {
+    let mut changes = vec![];
+    for (overlay, layers) in overlays.iter().zip(layers) {
+        let upper = PathBuf::from(format!("/proc/self/fd/{}/upper", layers.as_raw_fd()));
+        let mut overlay_changes = vec![];
+        diff(&overlay.lower, &upper, Path::new(""), &mut overlay_changes).with_context(|| {
+            format!("Failed to compute changes of {}", overlay.target.display())
+        })?;
+        if let Some(destination) = &overlay.copy_changes_to {
+            copy_changes(&upper, destination, &overlay_changes)
+                .with_context(|| format!("Failed to copy changes to {}", destination.display()))?;
+        }
+        changes.extend(overlay_changes.into_iter().map(|change| match change {
+            FileChange::Created(path) => FileChange::Created(overlay.target.join(path)),
+            FileChange::Modified(path) => FileChange::Modified(overlay.target.join(path)),
+            FileChange::Deleted(path) => FileChange::Deleted(overlay.target.join(path)),
+        }));
+    }
+    Ok(changes)
+}
+
+/// Compare the directory `relative` of the upper layer with the lower one, pushing the changes
+/// with paths relative to the root of the overlay.
+fn diff(lower: &Path, upper: &Path, relative: &Path, changes: &mut Vec<FileChange>) -> Result<()> {
+    let mut entries = fs::read_dir(upper.join(relative))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for name in &entries {
+        let path = relative.join(name);
+        let metadata = fs::symlink_metadata(upper.join(&path))?;
+        let original = fs::symlink_metadata(lower.join(&path)).ok();
+
+        // Deleted files are replaced by a character device with device number 0/0
+        if metadata.file_type().is_char_device() && metadata.rdev() == 0 {
+            changes.push(FileChange::Deleted(path));
+            continue;
+        }
+
+        match &original {
+            None => changes.push(FileChange::Created(path.clone())),
+            Some(original) if !metadata.is_dir() || !original.is_dir() => {
+                changes.push(FileChange::Modified(path.clone()))
+            }
+            // A directory that has only been traversed is not a change by itself
+            Some(_) => {}
+        }
+
+        if metadata.is_dir() {
+            // An opaque directory replaced a deleted one, hiding all of its content
+            if original.as_ref().is_some_and(|m| m.is_dir()) && is_opaque(&upper.join(&path)) {
+                for hidden in fs::read_dir(lower.join(&path))? {
+                    let hidden = hidden?.file_name();
+                    if !upper.join(&path).join(&hidden).exists() {
+                        changes.push(FileChange::Deleted(path.join(hidden)));
+                    }
+                }
+            }
+            diff(lower, upper, &path, changes)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check if the directory is marked as opaque by overlayfs
+fn is_opaque(path: &Path) -> bool {
+    let path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    let name = CString::new(OPAQUE_XATTR).unwrap();
+    let mut value = [0u8; 1];
+    let len = unsafe {
+        libc::lgetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_mut_ptr() as *mut libc::c_void,
+            value.len(),
+        )
+    };
+    len == 1 && value[0] == b'y'
+}
+
+/// Copy the created and modified files from the upper layer into `destination`
+fn copy_changes(upper: &Path, destination: &Path, changes: &[FileChange]) -> Result<()> {
+    fs::create_dir_all(destination)?;
+    for change in changes {
+        let path = match change {
+            FileChange::Created(path) | FileChange::Modified(path) => path,
+            FileChange::Deleted(_) => continue,
+        };
+        let source = upper.join(path);
+        let target = destination.join(path);
+        let metadata = fs::symlink_metadata(&source)?;
+        if metadata.is_dir() {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if metadata.file_type().is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(&source)?, &target)?;
+        } else if metadata.is_file() {
+            fs::copy(&source, &target).with_context(|| {
+                format!(
+                    "Failed to copy {} -> {}",
+                    source.display(),
+                    target.display()
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
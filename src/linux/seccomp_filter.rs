@@ -7,17 +7,46 @@ use std::ffi::CString;
 
 use anyhow::bail;
 
-use crate::syscall_filter::SyscallFilterAction;
+use crate::syscall_filter::{ArgumentCondition, ComparisonOperator, SyscallFilterAction};
 use crate::util::strerror;
 use crate::Result;
 
+/// Allow and log the system call, missing in seccomp-sys (requires Linux 4.14)
+const SCMP_ACT_LOG: u32 = 0x7ffc0000;
+
 impl SyscallFilterAction {
     /// Transform the Action to the correct seccomp parameter
     fn to_seccomp_param(self) -> u32 {
         match self {
             SyscallFilterAction::Allow => seccomp_sys::SCMP_ACT_ALLOW,
             SyscallFilterAction::Kill => seccomp_sys::SCMP_ACT_KILL,
+            SyscallFilterAction::KillProcess => seccomp_sys::SCMP_ACT_KILL_PROCESS,
             SyscallFilterAction::Errno(errno) => seccomp_sys::SCMP_ACT_ERRNO(errno),
+            SyscallFilterAction::Trap => seccomp_sys::SCMP_ACT_TRAP,
+            SyscallFilterAction::Log => SCMP_ACT_LOG,
+            SyscallFilterAction::Trace(message) => seccomp_sys::SCMP_ACT_TRACE(message as u32),
+        }
+    }
+}
+
+impl ArgumentCondition {
+    /// Transform the condition to a libseccomp comparator
+    fn to_seccomp_comparator(self) -> seccomp_sys::scmp_arg_cmp {
+        use seccomp_sys::scmp_compare::*;
+        let (op, datum_a, datum_b) = match self.operator {
+            ComparisonOperator::Equal => (SCMP_CMP_EQ, self.value, 0),
+            ComparisonOperator::NotEqual => (SCMP_CMP_NE, self.value, 0),
+            ComparisonOperator::Less => (SCMP_CMP_LT, self.value, 0),
+            ComparisonOperator::LessOrEqual => (SCMP_CMP_LE, self.value, 0),
+            ComparisonOperator::Greater => (SCMP_CMP_GT, self.value, 0),
+            ComparisonOperator::GreaterOrEqual => (SCMP_CMP_GE, self.value, 0),
+            ComparisonOperator::MaskedEqual(mask) => (SCMP_CMP_MASKED_EQ, mask, self.value),
+        };
+        seccomp_sys::scmp_arg_cmp {
+            arg: self.index,
+            op,
+            datum_a,
+            datum_b,
         }
     }
 }
@@ -40,7 +69,17 @@ impl SeccompFilter {
 
     /// Allow a syscall
     pub fn filter(&mut self, name: &str, action: SyscallFilterAction) -> Result<()> {
-        debug!("Add rule {} {:?}", name, action);
+        self.filter_conditional(name, action, &[])
+    }
+
+    /// Add a rule for a syscall, applied only when all the conditions on its arguments are met
+    pub fn filter_conditional(
+        &mut self,
+        name: &str,
+        action: SyscallFilterAction,
+        conditions: &[ArgumentCondition],
+    ) -> Result<()> {
+        debug!("Add rule {} {:?} {:?}", name, action, conditions);
         let syscall_name = CString::new(name).unwrap();
         let syscall_num =
             unsafe { seccomp_sys::seccomp_syscall_resolve_name(syscall_name.as_ptr()) };
@@ -50,11 +89,21 @@ impl SeccompFilter {
                 name
             );
         }
+        let comparators: Vec<_> = conditions
+            .iter()
+            .map(|condition| condition.to_seccomp_comparator())
+            .collect();
         if unsafe {
-            seccomp_sys::seccomp_rule_add(self.ctx, action.to_seccomp_param(), syscall_num, 0)
+            seccomp_sys::seccomp_rule_add_array(
+                self.ctx,
+                action.to_seccomp_param(),
+                syscall_num,
+                comparators.len() as libc::c_uint,
+                comparators.as_ptr(),
+            )
         } < 0
         {
-            bail!("Error calling seccomp_rule_add(): {}", strerror())
+            bail!("Error calling seccomp_rule_add_array(): {}", strerror())
         } else {
             Ok(())
         }
@@ -0,0 +1,392 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context};
+
+use crate::Result;
+
+/// Size of the blocks of a tar archive
+const BLOCK_SIZE: u64 = 512;
+
+/// Extract a tar archive (ustar, GNU or pax) into `destination`. Only regular files,
+/// directories, symbolic links and hard links are extracted.
+pub fn extract(archive: &Path, destination: &Path) -> Result<()> {
+    let file = File::open(archive)
+        .with_context(|| format!("Failed to open archive {}", archive.display()))?;
+    let mut reader = BufReader::new(file);
+
+    // Names that do not fit in the header are stored in a previous entry
+    let mut long_name: Option<PathBuf> = None;
+    let mut long_link: Option<PathBuf> = None;
+    // Permissions of the directories are set at the end, they may prevent the extraction
+    let mut directories = vec![];
+
+    loop {
+        let mut header = [0u8; BLOCK_SIZE as usize];
+        reader
+            .read_exact(&mut header)
+            .context("Failed to read tar header")?;
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        check_checksum(&header)?;
+
+        let size = parse_octal(&header[124..136]).context("Invalid entry size")?;
+        let mode = parse_octal(&header[100..108]).context("Invalid entry mode")? as u32 & 0o7777;
+        let kind = header[156];
+        let name = long_name.take().unwrap_or_else(|| header_name(&header));
+        let link = long_link
+            .take()
+            .unwrap_or_else(|| PathBuf::from(OsStr::from_bytes(field(&header[157..257]))));
+
+        let mut data = (&mut reader).take(size);
+        match kind {
+            b'L' => long_name = Some(read_path(&mut data)?),
+            b'K' => long_link = Some(read_path(&mut data)?),
+            b'x' => {
+                let mut records = vec![];
+                data.read_to_end(&mut records)?;
+                for (key, value) in parse_pax(&records)? {
+                    match key {
+                        b"path" => long_name = Some(PathBuf::from(OsStr::from_bytes(value))),
+                        b"linkpath" => long_link = Some(PathBuf::from(OsStr::from_bytes(value))),
+                        _ => {}
+                    }
+                }
+            }
+            b'0' | b'\0' | b'7' => {
+                let path = entry_path(destination, &name)?;
+                let mut file = File::create(&path)
+                    .with_context(|| format!("Failed to create {}", path.display()))?;
+                io::copy(&mut data, &mut file)
+                    .with_context(|| format!("Failed to extract {}", path.display()))?;
+                file.set_permissions(fs::Permissions::from_mode(mode))?;
+            }
+            b'5' => {
+                let path = entry_path(destination, &name)?;
+                if path != destination {
+                    fs::create_dir_all(&path)
+                        .with_context(|| format!("Failed to create {}", path.display()))?;
+                    directories.push((path, mode));
+                }
+            }
+            b'2' => {
+                let path = entry_path(destination, &name)?;
+                std::os::unix::fs::symlink(&link, &path)
+                    .with_context(|| format!("Failed to create symlink {}", path.display()))?;
+            }
+            b'1' => {
+                let path = entry_path(destination, &name)?;
+                let original = resolve(destination, &link)?;
+                fs::hard_link(&original, &path)
+                    .with_context(|| format!("Failed to create hard link {}", path.display()))?;
+            }
+            b'g' => {}
+            _ => warn!(
+                "Skipping entry {} of unsupported type {:?}",
+                name.display(),
+                kind as char
+            ),
+        }
+
+        // Skip what is left of the entry and the padding to the next block
+        io::copy(&mut data, &mut io::sink())?;
+        let padding = (BLOCK_SIZE - size % BLOCK_SIZE) % BLOCK_SIZE;
+        io::copy(&mut (&mut reader).take(padding), &mut io::sink())?;
+    }
+
+    for (path, mode) in directories.into_iter().rev() {
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Path where to extract an entry of the archive, creating its parent directories
+fn entry_path(destination: &Path, name: &Path) -> Result<PathBuf> {
+    let path = resolve(destination, name)?;
+    if path == destination {
+        return Ok(path);
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    // An entry replaces an existing symlink, instead of writing to where it points to
+    if is_symlink(&path) {
+        fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(path)
+}
+
+/// Path of an entry of the archive inside `destination`. Entries that are absolute, with `..` or
+/// passing through a symbolic link are rejected, since they could refer to files outside of
+/// `destination`.
+fn resolve(destination: &Path, name: &Path) -> Result<PathBuf> {
+    let mut path = destination.to_owned();
+    for component in name.components() {
+        match component {
+            Component::Normal(component) => {
+                if is_symlink(&path) {
+                    bail!("Archive entry {} passes through a symlink", name.display());
+                }
+                path.push(component);
+            }
+            Component::CurDir => {}
+            Component::RootDir | Component::ParentDir | Component::Prefix(_) => {
+                bail!("Invalid archive entry: {}", name.display())
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// Check if the path exists and it's a symbolic link
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+/// Name of the entry, joining the ustar prefix if present
+fn header_name(header: &[u8]) -> PathBuf {
+    let name = OsStr::from_bytes(field(&header[0..100]));
+    if &header[257..262] == b"ustar" {
+        let prefix = field(&header[345..500]);
+        if !prefix.is_empty() {
+            return Path::new(OsStr::from_bytes(prefix)).join(name);
+        }
+    }
+    PathBuf::from(name)
+}
+
+/// Content of a NUL-terminated header field
+fn field(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+/// Parse a numeric header field, in octal or in the GNU base-256 encoding
+fn parse_octal(bytes: &[u8]) -> Result<u64> {
+    if bytes[0] & 0x80 != 0 {
+        return Ok(bytes[1..]
+            .iter()
+            .fold(0, |acc, b| (acc << 8) | u64::from(*b)));
+    }
+    let text = std::str::from_utf8(field(bytes))?.trim();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    Ok(u64::from_str_radix(text, 8)?)
+}
+
+/// Verify the checksum of a header, computed with the checksum field filled with spaces
+fn check_checksum(header: &[u8]) -> Result<()> {
+    let expected = parse_octal(&header[148..156]).context("Invalid header checksum")?;
+    let actual: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            if (148..156).contains(&i) {
+                32
+            } else {
+                *b as u64
+            }
+        })
+        .sum();
+    if expected != actual {
+        bail!("Invalid tar header checksum, is this a tar archive?");
+    }
+    Ok(())
+}
+
+/// Read a path stored as the content of an entry
+fn read_path(data: &mut impl Read) -> Result<PathBuf> {
+    let mut path = vec![];
+    data.read_to_end(&mut path)?;
+    Ok(PathBuf::from(OsStr::from_bytes(field(&path))))
+}
+
+/// Parse the records of a pax extended header, in the form "<length> <key>=<value>\n"
+fn parse_pax(mut records: &[u8]) -> Result<Vec<(&[u8], &[u8])>> {
+    let mut result = vec![];
+    while !records.is_empty() {
+        let space = match records.iter().position(|b| *b == b' ') {
+            Some(space) => space,
+            None => bail!("Invalid pax record"),
+        };
+        let length: usize = std::str::from_utf8(&records[..space])?.parse()?;
+        if length <= space || length > records.len() {
+            bail!("Invalid pax record length");
+        }
+        let record = &records[space + 1..length - 1];
+        if let Some(equal) = record.iter().position(|b| *b == b'=') {
+            result.push((&record[..equal], &record[equal + 1..]));
+        }
+        records = &records[length..];
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+
+    use super::{extract, BLOCK_SIZE};
+
+    /// Builder of tar archives for the tests
+    #[derive(Default)]
+    struct Archive(Vec<u8>);
+
+    impl Archive {
+        /// Append an entry of the specified kind
+        fn entry(&mut self, kind: u8, name: &str, link: &str, data: &[u8]) -> &mut Self {
+            let mut header = [0u8; BLOCK_SIZE as usize];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[100..108].copy_from_slice(b"0000644\0");
+            header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+            header[156] = kind;
+            header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+            header[257..265].copy_from_slice(b"ustar\x0000");
+            header[148..156].fill(b' ');
+            let checksum: u64 = header.iter().map(|b| u64::from(*b)).sum();
+            header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+            self.0.extend_from_slice(&header);
+            self.0.extend_from_slice(data);
+            let block = BLOCK_SIZE as usize;
+            self.0
+                .resize(self.0.len() + (block - data.len() % block) % block, 0);
+            self
+        }
+
+        /// Append a pax extended header with the specified records
+        fn pax(&mut self, records: &[(&str, &str)]) -> &mut Self {
+            let mut data = String::new();
+            for (key, value) in records {
+                let record = format!(" {}={}\n", key, value);
+                // The length includes its own digits
+                let mut length = record.len() + 1;
+                while format!("{}{}", length, record).len() != length {
+                    length += 1;
+                }
+                data += &format!("{}{}", length, record);
+            }
+            self.entry(b'x', "pax", "", data.as_bytes())
+        }
+
+        /// Write the archive and extract it into `destination`
+        fn extract(&mut self, destination: &Path) -> anyhow::Result<()> {
+            self.0.resize(self.0.len() + 2 * BLOCK_SIZE as usize, 0);
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), &self.0).unwrap();
+            extract(file.path(), destination)
+        }
+    }
+
+    #[test]
+    fn test_reject_parent_dir() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let destination = dir.path().join("root");
+        fs::create_dir(&destination).unwrap();
+        let result = Archive::default()
+            .entry(b'0', "../evil", "", b"evil")
+            .extract(&destination);
+        assert!(result.is_err());
+        assert!(!dir.path().join("evil").exists());
+    }
+
+    #[test]
+    fn test_reject_absolute() {
+        let destination = tempfile::TempDir::new().unwrap();
+        let outside = tempfile::TempDir::new().unwrap();
+        let name = outside.path().join("evil");
+        let result = Archive::default()
+            .entry(b'0', name.to_str().unwrap(), "", b"evil")
+            .extract(destination.path());
+        assert!(result.is_err());
+        assert!(!name.exists());
+    }
+
+    #[test]
+    fn test_reject_through_symlink() {
+        let destination = tempfile::TempDir::new().unwrap();
+        let outside = tempfile::TempDir::new().unwrap();
+        let result = Archive::default()
+            .entry(b'2', "link", outside.path().to_str().unwrap(), b"")
+            .entry(b'0', "link/evil", "", b"evil")
+            .extract(destination.path());
+        assert!(result.is_err());
+        assert!(!outside.path().join("evil").exists());
+    }
+
+    #[test]
+    fn test_pax_paths() {
+        let destination = tempfile::TempDir::new().unwrap();
+        let long = format!("{}/file", "d".repeat(120));
+        Archive::default()
+            .pax(&[("path", &long), ("mtime", "0")])
+            .entry(b'0', "short", "", b"content")
+            .pax(&[("linkpath", &long)])
+            .entry(b'2', "link", "short", b"")
+            .extract(destination.path())
+            .unwrap();
+        let path = destination.path().join(&long);
+        assert_eq!(fs::read_to_string(path).unwrap(), "content");
+        assert!(!destination.path().join("short").exists());
+        let link = fs::read_link(destination.path().join("link")).unwrap();
+        assert_eq!(link, Path::new(&long));
+    }
+
+    #[test]
+    fn test_gnu_long_names() {
+        let destination = tempfile::TempDir::new().unwrap();
+        let long = format!("{}/file", "d".repeat(120));
+        let long_link = format!("/{}", "l".repeat(120));
+        Archive::default()
+            .entry(b'L', "././@LongLink", "", format!("{}\0", long).as_bytes())
+            .entry(b'0', "short", "", b"content")
+            .entry(
+                b'K',
+                "././@LongLink",
+                "",
+                format!("{}\0", long_link).as_bytes(),
+            )
+            .entry(b'2', "link", "short", b"")
+            .extract(destination.path())
+            .unwrap();
+        let path = destination.path().join(&long);
+        assert_eq!(fs::read_to_string(path).unwrap(), "content");
+        let link = fs::read_link(destination.path().join("link")).unwrap();
+        assert_eq!(link, Path::new(&long_link));
+    }
+
+    #[test]
+    fn test_hard_link() {
+        let destination = tempfile::TempDir::new().unwrap();
+        Archive::default()
+            .entry(b'0', "dir/original", "", b"content")
+            .entry(b'1', "link", "dir/original", b"")
+            .extract(destination.path())
+            .unwrap();
+        let original = fs::metadata(destination.path().join("dir/original")).unwrap();
+        let link = fs::metadata(destination.path().join("link")).unwrap();
+        assert_eq!(original.ino(), link.ino());
+        assert_eq!(link.nlink(), 2);
+
+        // A hard link can't point outside of the destination
+        let result = Archive::default()
+            .entry(b'1', "escape", "../outside", b"")
+            .extract(destination.path());
+        assert!(result.is_err());
+    }
+}
@@ -117,6 +117,10 @@ impl Sandbox for MacOSSandbox {
                 memory_usage: resource_usage.memory_usage / 1024, // on macOS memory usage is in bytes!
                 ..resource_usage
             },
+            file_changes: vec![],
+            collected_files: vec![],
+            collected_files_truncated: false,
+            deterministic: None,
         })
     }
 
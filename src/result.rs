@@ -5,6 +5,8 @@
 
 //! This module contains types for the result of an execution
 
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
 
 use crate::util::strsignal;
@@ -45,6 +47,45 @@ impl ExitStatus {
     }
 }
 
+/// A change made by the program to an overlay mount. The paths are the ones inside the sandbox.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum FileChange {
+    /// The path didn't exist and it was created
+    Created(PathBuf),
+
+    /// The path existed and it was modified
+    Modified(PathBuf),
+
+    /// The path existed and it was deleted
+    Deleted(PathBuf),
+}
+
+/// A file collected from the sandbox after the execution
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CollectedFile {
+    /// Path of the file inside the sandbox
+    pub path: PathBuf,
+
+    /// Size of the file in bytes
+    pub size: u64,
+
+    /// Content of the file, `None` if it was copied into the collect directory
+    pub contents: Option<Vec<u8>>,
+}
+
+/// Settings applied to the sandbox by the deterministic mode
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeterministicSettings {
+    /// Whether the address space layout randomization was disabled
+    pub aslr_disabled: bool,
+
+    /// Hostname inside the sandbox
+    pub hostname: String,
+
+    /// CPU core the sandbox was pinned to
+    pub cpu_core: usize,
+}
+
 /// struct that represents the execution result of a sandbox
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SandboxExecutionResult {
@@ -53,6 +94,22 @@ pub struct SandboxExecutionResult {
 
     /// Information about the resource usage of the process
     pub resource_usage: ResourceUsage,
+
+    /// Changes made by the program to the overlay mounts
+    #[serde(default)]
+    pub file_changes: Vec<FileChange>,
+
+    /// Files collected from the sandbox
+    #[serde(default)]
+    pub collected_files: Vec<CollectedFile>,
+
+    /// True if some files were not collected since they exceeded the size limit
+    #[serde(default)]
+    pub collected_files_truncated: bool,
+
+    /// Settings of the deterministic mode, if it was enabled (supported only on Linux)
+    #[serde(default)]
+    pub deterministic: Option<DeterministicSettings>,
 }
 
 impl ExitStatus {
@@ -13,11 +13,89 @@ pub enum SyscallFilterAction {
     /// Allow all system calls
     Allow,
 
-    /// Kill the process
+    /// Kill the thread that made the system call
     Kill,
 
+    /// Kill the whole process, including all of its threads
+    KillProcess,
+
     /// Return this errno
     Errno(u32),
+
+    /// Send SIGSYS to the thread that made the system call
+    Trap,
+
+    /// Allow the system call, logging it in the audit log
+    Log,
+
+    /// Notify the ptrace tracer with this message, the system call fails with ENOSYS if the
+    /// process is not traced
+    Trace(u16),
+}
+
+/// Comparison between an argument of a system call and a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ComparisonOperator {
+    /// The argument is equal to the value
+    Equal,
+
+    /// The argument is not equal to the value
+    NotEqual,
+
+    /// The argument is less than the value
+    Less,
+
+    /// The argument is less than or equal to the value
+    LessOrEqual,
+
+    /// The argument is greater than the value
+    Greater,
+
+    /// The argument is greater than or equal to the value
+    GreaterOrEqual,
+
+    /// The argument, in bitwise and with this mask, is equal to the value
+    MaskedEqual(u64),
+}
+
+/// Condition on an argument of a system call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArgumentCondition {
+    /// Index of the argument, starting from 0
+    pub index: u32,
+
+    /// How to compare the argument with the value
+    pub operator: ComparisonOperator,
+
+    /// The value to compare the argument with
+    pub value: u64,
+}
+
+impl ArgumentCondition {
+    /// A condition on the argument with the specified index
+    pub fn new(index: u32, operator: ComparisonOperator, value: u64) -> Self {
+        ArgumentCondition {
+            index,
+            operator,
+            value,
+        }
+    }
+}
+
+/// Rule applied only when all the conditions on the arguments of the system call are met
+///
+/// Only the arguments passed in registers can be checked: for example the flags of clone3 are in
+/// a struct in memory, and glibc uses clone3 for creating threads when it's available.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConditionalRule {
+    /// Name of the system call
+    pub syscall: String,
+
+    /// Action to execute when the conditions are met
+    pub action: SyscallFilterAction,
+
+    /// Conditions on the arguments of the system call
+    pub conditions: Vec<ArgumentCondition>,
 }
 
 /// Syscall filter configuration
@@ -28,13 +106,18 @@ pub struct SyscallFilter {
 
     /// Sandbox filter rules in the form of (syscall_name, action)
     pub rules: Vec<(String, SyscallFilterAction)>,
+
+    /// Sandbox filter rules that depend on the arguments of the system call
+    #[serde(default)]
+    pub conditional_rules: Vec<ConditionalRule>,
 }
 
 impl Default for SyscallFilter {
     fn default() -> Self {
         SyscallFilter {
-            default_action: SyscallFilterAction::Kill,
+            default_action: SyscallFilterAction::KillProcess,
             rules: Vec::new(),
+            conditional_rules: Vec::new(),
         }
     }
 }
@@ -45,17 +128,17 @@ impl SyscallFilter {
         let mut filter = SyscallFilter::default();
         filter.default_action(SyscallFilterAction::Allow);
         if !multiprocess {
-            filter.add_rule("fork", SyscallFilterAction::Kill);
-            filter.add_rule("vfork", SyscallFilterAction::Kill);
-            filter.add_rule("clone", SyscallFilterAction::Kill);
+            filter.add_rule("fork", SyscallFilterAction::KillProcess);
+            filter.add_rule("vfork", SyscallFilterAction::KillProcess);
+            filter.add_rule("clone", SyscallFilterAction::KillProcess);
         }
         if !chmod {
-            filter.add_rule("chmod", SyscallFilterAction::Kill);
-            filter.add_rule("fchmod", SyscallFilterAction::Kill);
-            filter.add_rule("fchmodat", SyscallFilterAction::Kill);
+            filter.add_rule("chmod", SyscallFilterAction::KillProcess);
+            filter.add_rule("fchmod", SyscallFilterAction::KillProcess);
+            filter.add_rule("fchmodat", SyscallFilterAction::KillProcess);
         }
-        filter.add_rule("setrlimit", SyscallFilterAction::Kill);
-        filter.add_rule("chroot", SyscallFilterAction::Kill);
+        filter.add_rule("setrlimit", SyscallFilterAction::KillProcess);
+        filter.add_rule("chroot", SyscallFilterAction::KillProcess);
         filter
     }
 
@@ -74,4 +157,19 @@ impl SyscallFilter {
         self.rules.push((syscall.into(), action));
         self
     }
+
+    /// Add a rule to the filter, applied only when all the conditions on the arguments are met
+    pub fn add_conditional_rule<S: Into<String>>(
+        &mut self,
+        syscall: S,
+        action: SyscallFilterAction,
+        conditions: Vec<ArgumentCondition>,
+    ) -> &mut Self {
+        self.conditional_rules.push(ConditionalRule {
+            syscall: syscall.into(),
+            action,
+            conditions,
+        });
+        self
+    }
 }
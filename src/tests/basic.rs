@@ -53,3 +53,229 @@ fn test_env() {
     assert_eq!(result.result.status, ExitStatus::ExitCode(0));
     assert_eq!(result.stdout, "42");
 }
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_extra_fd() {
+    use std::os::unix::io::AsRawFd;
+
+    let program = r#"
+        #include <unistd.h>
+        int main() { return write(4, "42", 2) != 2; }
+    "#;
+
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("fd4.txt");
+    let file = std::fs::File::create(&path).unwrap();
+
+    let mut config = SandboxConfiguration::default();
+    config.extra_fd(file.as_raw_fd(), 4);
+    let result = exec(program, &mut config, "");
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "42");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_mount_file() {
+    let program = r#"
+        #include <stdio.h>
+        int main() {
+            char buf[16] = {};
+            FILE *f = fopen("/data/input.txt", "r");
+            if (!f) return 1;
+            fgets(buf, sizeof(buf), f);
+            printf("%s", buf);
+            return fopen("/data/input.txt", "w") != NULL;
+        }
+    "#;
+
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("input.txt");
+    std::fs::write(&path, "42").unwrap();
+
+    let mut config = SandboxConfiguration::default();
+    config.mount(path, "/data/input.txt", false);
+    let result = exec(program, &mut config, "");
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert_eq!(result.stdout, "42");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_exec_from_host() {
+    use std::path::Path;
+
+    use crate::{Sandbox, SandboxImplementation};
+
+    // The directory with the executable is not mounted in the sandbox
+    let temp = tempfile::TempDir::new().unwrap();
+    let executable = temp.path().join("echo");
+    std::fs::copy("/bin/echo", &executable).unwrap();
+    let stdout = temp.path().join("stdout.txt");
+
+    let mut config = SandboxConfiguration::default();
+    config
+        .executable(&executable)
+        .exec_from_host(true)
+        .arg("-n")
+        .arg("hello")
+        .stdout(&stdout);
+    for dir in ["/usr", "/lib", "/lib64", "/etc"] {
+        if Path::new(dir).exists() {
+            config.mount(dir, dir, false);
+        }
+    }
+
+    let sandbox = SandboxImplementation::run(config.build()).unwrap();
+    let result = sandbox.wait().unwrap();
+
+    assert_eq!(result.status, ExitStatus::ExitCode(0));
+    assert_eq!(std::fs::read_to_string(stdout).unwrap(), "hello");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_exec_from_host_script() {
+    use std::os::unix::fs::PermissionsExt;
+
+    use crate::{Sandbox, SandboxImplementation};
+
+    let temp = tempfile::TempDir::new().unwrap();
+    let executable = temp.path().join("script.sh");
+    std::fs::write(&executable, "#!/bin/sh\necho hello\n").unwrap();
+    std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut config = SandboxConfiguration::default();
+    config.executable(&executable).exec_from_host(true);
+
+    let sandbox = SandboxImplementation::run(config.build()).unwrap();
+    let error = format!("{:#}", sandbox.wait().unwrap_err());
+    assert!(error.contains("is not an ELF executable"), "{}", error);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_network_loopback() {
+    use crate::configuration::Network;
+
+    let program = r#"
+       #include <arpa/inet.h>
+       #include <netinet/in.h>
+       #include <stdio.h>
+       #include <sys/socket.h>
+       #include <unistd.h>
+       int main() {
+           int server = socket(AF_INET, SOCK_STREAM, 0);
+           struct sockaddr_in addr = {0};
+           addr.sin_family = AF_INET;
+           addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+           socklen_t len = sizeof(addr);
+           if (bind(server, (struct sockaddr*)&addr, len) || listen(server, 1)) return 1;
+           getsockname(server, (struct sockaddr*)&addr, &len);
+           int client = socket(AF_INET, SOCK_STREAM, 0);
+           if (connect(client, (struct sockaddr*)&addr, len)) return 2;
+           int conn = accept(server, NULL, NULL);
+           char buf[8] = {};
+           write(client, "ping", 4);
+           read(conn, buf, sizeof(buf) - 1);
+           printf("%s", buf);
+           return 0;
+       }
+    "#;
+
+    let mut config = SandboxConfiguration::default();
+    let result = exec(program, &mut config, "");
+    assert_eq!(result.result.status, ExitStatus::ExitCode(2));
+
+    let mut config = SandboxConfiguration::default();
+    config.network(Network::Loopback);
+    let result = exec(program, &mut config, "");
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert_eq!(result.stdout, "ping");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_time_namespace() {
+    use crate::configuration::ClockOffsets;
+
+    let program = r#"
+       #include <stdio.h>
+       #include <time.h>
+       int main() {
+           struct timespec monotonic, boottime;
+           clock_gettime(CLOCK_MONOTONIC, &monotonic);
+           clock_gettime(CLOCK_BOOTTIME, &boottime);
+           printf("%ld %ld", (long)monotonic.tv_sec, (long)boottime.tv_sec);
+           return 0;
+       }
+    "#;
+
+    let host_clock = |clock| {
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(clock, &mut time) };
+        time.tv_sec
+    };
+
+    let mut config = SandboxConfiguration::default();
+    config.time_namespace(ClockOffsets {
+        monotonic: 1_000_000,
+        boottime: 2_000_000,
+    });
+    let before = (
+        host_clock(libc::CLOCK_MONOTONIC),
+        host_clock(libc::CLOCK_BOOTTIME),
+    );
+    let result = exec(program, &mut config, "");
+    let after = (
+        host_clock(libc::CLOCK_MONOTONIC),
+        host_clock(libc::CLOCK_BOOTTIME),
+    );
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    let values: Vec<libc::time_t> = result
+        .stdout
+        .split(' ')
+        .map(|value| value.parse().unwrap())
+        .collect();
+    assert!((before.0 + 1_000_000..=after.0 + 1_000_000).contains(&values[0]));
+    assert!((before.1 + 2_000_000..=after.1 + 2_000_000).contains(&values[1]));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_deterministic() {
+    let program = r#"
+       #define _GNU_SOURCE
+       #include <sched.h>
+       #include <stdio.h>
+       #include <stdlib.h>
+       #include <unistd.h>
+       int main() {
+           int local;
+           char hostname[64];
+           gethostname(hostname, sizeof(hostname));
+           printf("%p %p %s %d", (void*)&local, malloc(16), hostname, sched_getcpu());
+           return 0;
+       }
+    "#;
+
+    let run = || {
+        let mut config = SandboxConfiguration::default();
+        config.deterministic(true);
+        exec(program, &mut config, "")
+    };
+    let first = run();
+    let second = run();
+
+    assert_eq!(first.result.status, ExitStatus::ExitCode(0));
+    assert_eq!(first.stdout, second.stdout);
+    let settings = first.result.deterministic.unwrap();
+    assert!(settings.aslr_disabled);
+    let expected = format!(" {} {}", settings.hostname, settings.cpu_core);
+    assert!(first.stdout.ends_with(&expected), "{}", first.stdout);
+}
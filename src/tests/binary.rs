@@ -73,6 +73,51 @@ fn test_echo() {
     assert_eq!(output.stdout, "Hello, world!");
 }
 
+/// Test running a program with only its libraries mounted
+#[cfg(target_os = "linux")]
+#[test]
+fn test_auto_mount() {
+    let output = run_program(vec![
+        "--json",
+        "--auto-mount",
+        "--",
+        "/bin/echo",
+        "-n",
+        "Hello",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(std::str::from_utf8(&output.stdout).unwrap(), "Hello");
+    let result: crate::result::SandboxExecutionResult =
+        serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(result.status, ExitStatus::ExitCode(0));
+}
+
+/// Test loading the syscall filter from a file
+#[cfg(target_os = "linux")]
+#[test]
+fn test_syscall_filter_file() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let filter = temp.path().join("filter.json");
+    std::fs::write(
+        &filter,
+        r#"{"default_action": "Allow", "rules": [["exit_group", "Trap"]]}"#,
+    )
+    .unwrap();
+
+    let output = run_program(vec![
+        "--json",
+        "--auto-mount",
+        "--syscall-filter",
+        filter.to_str().unwrap(),
+        "--",
+        "/bin/true",
+    ]);
+    assert!(output.status.success());
+    let result: crate::result::SandboxExecutionResult =
+        serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(result.status, ExitStatus::Signal(libc::SIGSYS));
+}
+
 /// Test no ping
 #[cfg(target_os = "linux")]
 #[test]
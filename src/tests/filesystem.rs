@@ -0,0 +1,544 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+// SPDX-License-Identifier: MPL-2.0
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::util::*;
+use crate::configuration::{OverlayMount, RootFilesystem, SandboxConfiguration};
+use crate::result::{ExitStatus, FileChange};
+
+#[test]
+fn test_overlay() {
+    let program = r#"
+       #include <stdio.h>
+       #include <sys/stat.h>
+       #include <unistd.h>
+       int main() {
+           FILE *f = fopen("/data/a", "a");
+           if (!f) return 1;
+           fprintf(f, "changed");
+           fclose(f);
+           if (unlink("/data/b")) return 2;
+           if (mkdir("/data/dir/new", 0755)) return 3;
+           f = fopen("/data/dir/new/c", "w");
+           if (!f) return 4;
+           fprintf(f, "created");
+           fclose(f);
+           return 0;
+       }
+    "#;
+
+    let lower = tempfile::TempDir::new().unwrap();
+    fs::write(lower.path().join("a"), "original").unwrap();
+    fs::write(lower.path().join("b"), "original").unwrap();
+    fs::create_dir(lower.path().join("dir")).unwrap();
+    let copy = tempfile::TempDir::new().unwrap();
+
+    let mut overlay = OverlayMount::new(lower.path(), "/data");
+    overlay.copy_changes_to = Some(copy.path().to_owned());
+    let mut config = SandboxConfiguration::default();
+    config.overlay(overlay);
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert_eq!(
+        result.result.file_changes,
+        vec![
+            FileChange::Modified(PathBuf::from("/data/a")),
+            FileChange::Deleted(PathBuf::from("/data/b")),
+            FileChange::Created(PathBuf::from("/data/dir/new")),
+            FileChange::Created(PathBuf::from("/data/dir/new/c")),
+        ]
+    );
+    // the host directory is untouched
+    assert_eq!(
+        fs::read_to_string(lower.path().join("a")).unwrap(),
+        "original"
+    );
+    assert!(lower.path().join("b").exists());
+    assert!(!lower.path().join("dir/new").exists());
+    // the changes are copied out
+    assert_eq!(
+        fs::read_to_string(copy.path().join("a")).unwrap(),
+        "originalchanged"
+    );
+    assert_eq!(
+        fs::read_to_string(copy.path().join("dir/new/c")).unwrap(),
+        "created"
+    );
+    assert!(!copy.path().join("b").exists());
+}
+
+#[test]
+fn test_overlay_size() {
+    let program = r#"
+       #include <errno.h>
+       #include <fcntl.h>
+       #include <unistd.h>
+       int main() {
+           char buf[1024] = {};
+           int fd = open("/data/big", O_WRONLY | O_CREAT, 0644);
+           if (fd < 0) return 1;
+           // the layers don't have space for 1 MiB
+           for (int i = 0; i < 1024; i++)
+               if (write(fd, buf, sizeof(buf)) < 0) return errno == ENOSPC ? 0 : 2;
+           return 3;
+       }
+    "#;
+
+    let lower = tempfile::TempDir::new().unwrap();
+    let mut overlay = OverlayMount::new(lower.path(), "/data");
+    overlay.size = 64 * 1024;
+    let mut config = SandboxConfiguration::default();
+    config.overlay(overlay);
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert!(!lower.path().join("big").exists());
+}
+
+const ROOTFS_TEST_SRC: &str = r#"
+    #include <stdio.h>
+    int main() {
+        char buf[16] = {};
+        FILE *f = fopen("/link", "r");
+        if (!f) return 1;
+        fgets(buf, sizeof(buf), f);
+        printf("%s", buf);
+        return fopen("/opt/data/file", "w") != NULL;
+    }
+"#;
+
+/// Create a directory with the content of the root used in the rootfs tests
+fn rootfs_dir() -> tempfile::TempDir {
+    let root = tempfile::TempDir::new().unwrap();
+    fs::create_dir_all(root.path().join("opt/data")).unwrap();
+    fs::write(root.path().join("opt/data/file"), "42").unwrap();
+    std::os::unix::fs::symlink("opt/data/file", root.path().join("link")).unwrap();
+    root
+}
+
+#[test]
+fn test_rootfs_directory() {
+    let root = rootfs_dir();
+
+    let mut config = SandboxConfiguration::default();
+    config.rootfs(RootFilesystem::Directory(root.path().to_owned()));
+
+    let result = exec(ROOTFS_TEST_SRC, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert_eq!(result.stdout, "42");
+}
+
+#[test]
+fn test_rootfs_tarball() {
+    let root = rootfs_dir();
+    let archive = tempfile::TempDir::new().unwrap();
+    let archive = archive.path().join("rootfs.tar");
+    let status = std::process::Command::new("tar")
+        .arg("-cf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(root.path())
+        .arg(".")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut config = SandboxConfiguration::default();
+    config.rootfs(RootFilesystem::Tarball(archive));
+
+    let result = exec(ROOTFS_TEST_SRC, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert_eq!(result.stdout, "42");
+}
+
+#[test]
+fn test_inline_files() {
+    use crate::{Sandbox, SandboxImplementation};
+
+    let program = r#"
+       #include <stdio.h>
+       #include <stdlib.h>
+       #include <sys/stat.h>
+       int main() {
+           char buf[16] = {};
+           FILE *f = fopen("/input/data.txt", "r");
+           if (!f) return 1;
+           fgets(buf, sizeof(buf), f);
+           printf("%s", buf);
+           struct stat st;
+           if (stat("/input/data.txt", &st) || (st.st_mode & 0777) != 0640) return 2;
+           if (stat("/tmp/script.sh", &st) || (st.st_mode & 0777) != 0755) return 3;
+           return system("/tmp/script.sh");
+       }
+    "#;
+
+    let mut config = SandboxConfiguration::default();
+    config.file("/input/data.txt", "42", 0o640).file(
+        "/tmp/script.sh",
+        "#!/bin/sh\nexit 0\n",
+        0o755,
+    );
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert_eq!(result.stdout, "42");
+
+    // The root can't be replaced by a file
+    let mut config = SandboxConfiguration::default();
+    config.executable("/bin/true").file("/", "", 0o644);
+    let sandbox = SandboxImplementation::run(config.build()).unwrap();
+    let error = format!("{:#}", sandbox.wait().unwrap_err());
+    assert!(error.contains("Cannot create a file at /"), "{}", error);
+}
+
+const COLLECT_TEST_SRC: &str = r#"
+    #include <stdio.h>
+    #include <sys/stat.h>
+    #include <unistd.h>
+    void write_file(const char *path, const char *content) {
+        FILE *f = fopen(path, "w");
+        fprintf(f, "%s", content);
+        fclose(f);
+    }
+    int main() {
+        mkdir("/tmp/out", 0755);
+        mkdir("/scratch/deep", 0755);
+        write_file("/tmp/out/a.txt", "aaaa");
+        write_file("/tmp/out/b.txt", "bb");
+        write_file("/tmp/out/c.log", "c");
+        write_file("/scratch/deep/d.txt", "dddddddd");
+        symlink("/etc/passwd", "/tmp/out/e.txt");
+        return 0;
+    }
+"#;
+
+#[test]
+fn test_collect_files() {
+    use crate::configuration::TmpfsMount;
+
+    let mut config = SandboxConfiguration::default();
+    config
+        .tmpfs(TmpfsMount::new("/scratch", 1024 * 1024))
+        .collect("/tmp/out/*.txt")
+        .collect("/scratch/**")
+        .collect("/etc/*");
+
+    let result = exec(COLLECT_TEST_SRC, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    let files: Vec<_> = result
+        .result
+        .collected_files
+        .iter()
+        .map(|file| {
+            (
+                file.path.to_str().unwrap(),
+                file.contents.as_deref().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            ("/scratch/deep/d.txt", &b"dddddddd"[..]),
+            ("/tmp/out/a.txt", &b"aaaa"[..]),
+            ("/tmp/out/b.txt", &b"bb"[..]),
+        ]
+    );
+    assert!(!result.result.collected_files_truncated);
+}
+
+#[test]
+fn test_collect_files_leading_globstar() {
+    use crate::configuration::TmpfsMount;
+
+    // Only the writable areas are searched, not the read-only mounts like /usr
+    let mut config = SandboxConfiguration::default();
+    config
+        .tmpfs(TmpfsMount::new("/scratch", 1024 * 1024))
+        .collect("**/?.txt");
+
+    let result = exec(COLLECT_TEST_SRC, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    let paths: Vec<_> = result
+        .result
+        .collected_files
+        .iter()
+        .map(|file| file.path.to_str().unwrap())
+        .collect();
+    assert_eq!(
+        paths,
+        vec!["/scratch/deep/d.txt", "/tmp/out/a.txt", "/tmp/out/b.txt"]
+    );
+}
+
+#[test]
+fn test_collect_files_directory_limit() {
+    use crate::configuration::TmpfsMount;
+
+    let output = tempfile::TempDir::new().unwrap();
+    let mut config = SandboxConfiguration::default();
+    config
+        .tmpfs(TmpfsMount::new("/scratch", 1024 * 1024))
+        .collect("/tmp/out/*.txt")
+        .collect("/scratch/**/*.txt")
+        .collect_directory(output.path())
+        .collect_size_limit(10);
+
+    let result = exec(COLLECT_TEST_SRC, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert!(result.result.collected_files_truncated);
+    let paths: Vec<_> = result
+        .result
+        .collected_files
+        .iter()
+        .map(|file| file.path.to_str().unwrap())
+        .collect();
+    assert_eq!(paths, vec!["/scratch/deep/d.txt", "/tmp/out/b.txt"]);
+    assert!(result
+        .result
+        .collected_files
+        .iter()
+        .all(|file| file.contents.is_none()));
+    assert_eq!(
+        fs::read_to_string(output.path().join("scratch/deep/d.txt")).unwrap(),
+        "dddddddd"
+    );
+    assert_eq!(
+        fs::read_to_string(output.path().join("tmp/out/b.txt")).unwrap(),
+        "bb"
+    );
+    assert!(!output.path().join("tmp/out/a.txt").exists());
+}
+
+#[test]
+fn test_devices() {
+    let program = r#"
+       #include <errno.h>
+       #include <fcntl.h>
+       #include <stdio.h>
+       #include <unistd.h>
+       int main() {
+           int fd = open("/dev/full", O_WRONLY);
+           if (fd < 0) return 1;
+           if (write(fd, "x", 1) != -1 || errno != ENOSPC) return 2;
+           if (access("/dev/random", F_OK) == 0) return 3;
+           FILE *f = fopen("/dev/stdout", "w");
+           if (!f) return 4;
+           fprintf(f, "hello");
+           fclose(f);
+           return 0;
+       }
+    "#;
+
+    let mut config = SandboxConfiguration::default();
+    config
+        .mount_proc(true)
+        .device("full")
+        .remove_device("random");
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert_eq!(result.stdout, "hello");
+}
+
+#[test]
+fn test_sysfs() {
+    use crate::configuration::SysfsMount;
+
+    let program = r#"
+       #include <fcntl.h>
+       #include <unistd.h>
+       int main() {
+           if (access("/sys/devices/system/cpu/online", R_OK) != 0) return 1;
+           if (open("/sys/devices/system/cpu/online", O_WRONLY) >= 0) return 2;
+           return access("/sys/kernel", F_OK) == 0 ? 10 : 11;
+       }
+    "#;
+
+    let mut config = SandboxConfiguration::default();
+    config.mount_sys(SysfsMount::Full);
+    let result = exec(program, &mut config, "");
+    assert_eq!(result.result.status, ExitStatus::ExitCode(10));
+
+    let mut config = SandboxConfiguration::default();
+    config.mount_sys(SysfsMount::CpuTopology);
+    let result = exec(program, &mut config, "");
+    assert_eq!(result.result.status, ExitStatus::ExitCode(11));
+}
+
+#[test]
+fn test_generate_etc() {
+    let program = r#"
+       #include <pwd.h>
+       #include <grp.h>
+       #include <netdb.h>
+       #include <stdio.h>
+       #include <unistd.h>
+       int main() {
+           struct passwd *pw = getpwuid(getuid());
+           if (!pw) return 1;
+           struct group *gr = getgrgid(getgid());
+           if (!gr) return 2;
+           char hostname[64];
+           if (gethostname(hostname, sizeof(hostname))) return 3;
+           if (!gethostbyname("localhost")) return 4;
+           printf("%s %s %s", pw->pw_name, gr->gr_name, hostname);
+           return 0;
+       }
+    "#;
+
+    let mut config = SandboxConfiguration::default();
+    config
+        .uid(1000)
+        .gid(1000)
+        .hostname("box")
+        .generate_etc(true);
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert_eq!(result.stdout, "user user box");
+}
+
+#[test]
+fn test_generate_etc_read_only() {
+    use crate::{Sandbox, SandboxImplementation};
+
+    // The /etc of the rootfs is mounted read-only and has no hostname file
+    let root = tempfile::TempDir::new().unwrap();
+    fs::create_dir(root.path().join("etc")).unwrap();
+    for name in ["passwd", "group", "hosts", "nsswitch.conf"] {
+        fs::write(root.path().join("etc").join(name), "").unwrap();
+    }
+    let output = tempfile::TempDir::new().unwrap();
+    let stdout = output.path().join("stdout.txt");
+
+    let mut config = SandboxConfiguration::default();
+    config
+        .rootfs(RootFilesystem::Directory(root.path().to_owned()))
+        .mount("/usr", "/usr", false)
+        .mount("/lib", "/lib", false)
+        .mount("/bin", "/bin", false)
+        .uid(1000)
+        .gid(1000)
+        .generate_etc(true)
+        .executable("/bin/cat")
+        .arg("/etc/passwd")
+        .stdout(&stdout);
+    if Path::new("/lib64").exists() {
+        config.mount("/lib64", "/lib64", false);
+    }
+
+    let sandbox = SandboxImplementation::run(config.build()).unwrap();
+    let result = sandbox.wait().unwrap();
+
+    assert_eq!(result.status, ExitStatus::ExitCode(0));
+    assert!(fs::read_to_string(&stdout)
+        .unwrap()
+        .contains("user:x:1000:1000"));
+    assert!(!root.path().join("etc/hostname").exists());
+}
+
+#[test]
+fn test_rootfs_absolute_symlink() {
+    use crate::{Sandbox, SandboxImplementation};
+
+    // The /etc of the image points to a directory that also exists on the host
+    let outside = tempfile::TempDir::new().unwrap();
+    let root = tempfile::TempDir::new().unwrap();
+    std::os::unix::fs::symlink(outside.path(), root.path().join("etc")).unwrap();
+    let output = tempfile::TempDir::new().unwrap();
+    let stdout = output.path().join("stdout.txt");
+
+    let mut config = SandboxConfiguration::default();
+    config
+        .rootfs(RootFilesystem::Directory(root.path().to_owned()))
+        .mount("/usr", "/usr", false)
+        .mount("/lib", "/lib", false)
+        .mount("/bin", "/bin", false)
+        .uid(1000)
+        .gid(1000)
+        .generate_etc(true)
+        .file("/etc/motd", "hello\n", 0o644)
+        .executable("/bin/cat")
+        .arg("/etc/passwd")
+        .arg("/etc/motd")
+        .stdout(&stdout);
+    if Path::new("/lib64").exists() {
+        config.mount("/lib64", "/lib64", false);
+    }
+
+    let sandbox = SandboxImplementation::run(config.build()).unwrap();
+    let result = sandbox.wait().unwrap();
+
+    assert_eq!(result.status, ExitStatus::ExitCode(0));
+    let stdout = fs::read_to_string(&stdout).unwrap();
+    assert!(stdout.contains("user:x:1000:1000"), "{}", stdout);
+    assert!(stdout.ends_with("hello\n"), "{}", stdout);
+    // Inside the sandbox the symlink points to a directory of the sandbox
+    assert_eq!(fs::read_dir(outside.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_socket() {
+    use std::io::Write;
+    use std::os::unix::net::UnixListener;
+
+    use crate::{Sandbox, SandboxImplementation};
+
+    let program = r#"
+       #include <stdio.h>
+       #include <string.h>
+       #include <sys/socket.h>
+       #include <sys/un.h>
+       #include <unistd.h>
+       int main() {
+           int fd = socket(AF_UNIX, SOCK_STREAM, 0);
+           struct sockaddr_un addr = {0};
+           addr.sun_family = AF_UNIX;
+           strcpy(addr.sun_path, "/run/service.sock");
+           if (connect(fd, (struct sockaddr*)&addr, sizeof(addr))) return 1;
+           char buf[8] = {};
+           read(fd, buf, sizeof(buf) - 1);
+           printf("%s", buf);
+           return 0;
+       }
+    "#;
+
+    let temp = tempfile::TempDir::new().unwrap();
+    let path = temp.path().join("service.sock");
+    let listener = UnixListener::bind(&path).unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"pong").unwrap();
+    });
+
+    let mut config = SandboxConfiguration::default();
+    config.socket(&path, "/run/service.sock");
+    let result = exec(program, &mut config, "");
+    server.join().unwrap();
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert_eq!(result.stdout, "pong");
+
+    // Only sockets can be bound
+    let file = temp.path().join("file");
+    fs::write(&file, "").unwrap();
+    let mut config = SandboxConfiguration::default();
+    config.executable("/bin/true").socket(&file, "/run/file");
+    let sandbox = SandboxImplementation::run(config.build()).unwrap();
+    assert!(sandbox.wait().is_err());
+}
@@ -57,7 +57,7 @@ int main() {
 
 #[test]
 // macOS has a much lower stack limit than Linux (~8Mb and a maximum of 64)
-#[cfg(os = "linux")]
+#[cfg(target_os = "linux")]
 fn test_stack_limit_ok() {
     let mut config = SandboxConfiguration::default();
     config
@@ -72,7 +72,7 @@ fn test_stack_limit_ok() {
 
 #[test]
 // macOS has a much lower stack limit than Linux (~8Mb and a maximum of 64)
-#[cfg(os = "linux")]
+#[cfg(target_os = "linux")]
 fn test_stack_limit_default() {
     let mut config = SandboxConfiguration::default();
     config.memory_limit(100 * 1_000_000);
@@ -179,3 +179,34 @@ fn test_wall_time_exceeded() {
             && result.result.resource_usage.wall_time_usage < 1.1
     )
 }
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_tmpfs_size_limit() {
+    use crate::configuration::TmpfsMount;
+
+    let program = r#"
+       #include <stdio.h>
+       #include <string.h>
+       int main() {
+           static char buf[1024 * 1024];
+           memset(buf, 42, sizeof(buf));
+           FILE *ok = fopen("/scratch/data/ok", "w");
+           if (!ok || fwrite(buf, 1, sizeof(buf), ok) != sizeof(buf) || fclose(ok)) return 1;
+           FILE *big = fopen("/scratch/data/big", "w");
+           if (!big) return 2;
+           size_t written = 0;
+           for (int i = 0; i < 2; i++) written += fwrite(buf, 1, sizeof(buf), big);
+           return written == 2 * sizeof(buf) ? 3 : 0;
+       }
+    "#;
+
+    let mut tmpfs = TmpfsMount::new("/scratch/data", 2 * 1024 * 1024);
+    tmpfs.nr_inodes = Some(16);
+    let mut config = SandboxConfiguration::default();
+    config.tmpfs(tmpfs);
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+}
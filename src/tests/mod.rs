@@ -5,6 +5,8 @@
 
 mod basic;
 mod binary;
+#[cfg(target_os = "linux")]
+mod filesystem;
 mod limits;
 
 #[cfg(target_os = "linux")]
@@ -88,3 +88,400 @@ fn test_write_tmp() {
 
     assert_eq!(result.result.status, ExitStatus::ExitCode(1));
 }
+
+#[test]
+fn test_no_inherited_fds() {
+    let program = r#"
+       #include <dirent.h>
+       #include <stdio.h>
+       #include <stdlib.h>
+       int main() {
+           DIR *dir = opendir("/proc/self/fd");
+           struct dirent *entry;
+           int unexpected = 0;
+           while ((entry = readdir(dir)) != NULL) {
+               if (entry->d_name[0] == '.') continue;
+               int fd = atoi(entry->d_name);
+               if (fd > 2 && fd != dirfd(dir)) {
+                   printf("%d ", fd);
+                   unexpected++;
+               }
+           }
+           return unexpected;
+       }
+    "#;
+
+    // simulate a descriptor leaked by the application, without O_CLOEXEC
+    let leaked = unsafe { libc::dup(libc::STDERR_FILENO) };
+    assert!(leaked >= 0);
+
+    let mut config = SandboxConfiguration::default();
+    config.mount_proc(true);
+
+    let result = exec(program, &mut config, "");
+    unsafe { libc::close(leaked) };
+
+    assert_eq!(result.stdout, "");
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+}
+
+#[test]
+fn test_noexec_writable_mount() {
+    use crate::configuration::MountOptions;
+
+    let program = r#"
+       #include <errno.h>
+       #include <stdio.h>
+       #include <unistd.h>
+       int main() {
+           FILE *f = fopen("/scratch/file", "w");
+           if (!f) return 1;
+           fclose(f);
+           char *args[] = {"/scratch/true", NULL};
+           execv(args[0], args);
+           return errno == EACCES ? 0 : 2;
+       }
+    "#;
+
+    let scratch = tempfile::TempDir::new().unwrap();
+    std::fs::copy("/bin/true", scratch.path().join("true")).unwrap();
+
+    let options = MountOptions {
+        noexec: true,
+        ..Default::default()
+    };
+    let mut config = SandboxConfiguration::default();
+    config.mount_with_options(scratch.path(), "/scratch", true, options);
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert!(scratch.path().join("file").exists());
+}
+
+#[test]
+fn test_no_leaked_mounts() {
+    use crate::configuration::{OverlayMount, TmpfsMount};
+
+    let program = r#"
+       int main() { return 0; }
+    "#;
+
+    let lower = tempfile::TempDir::new().unwrap();
+    let mut config = SandboxConfiguration::default();
+    config
+        .mount_proc(true)
+        .tmpfs(TmpfsMount::new("/scratch", 1024 * 1024))
+        .overlay(OverlayMount::new(lower.path(), "/data"));
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap();
+    assert!(!mountinfo.contains("/tabox-"), "{}", mountinfo);
+}
+
+#[test]
+fn test_no_leaked_mounts_on_error() {
+    use crate::configuration::TmpfsMount;
+    use crate::{Sandbox, SandboxImplementation};
+
+    // The tmpfs is mounted before the bind mount with the missing source fails
+    let missing = tempfile::TempDir::new().unwrap().path().join("missing");
+    let mut config = SandboxConfiguration::default();
+    config
+        .executable("/bin/true")
+        .mount_proc(true)
+        .tmpfs(TmpfsMount::new("/scratch", 1024 * 1024))
+        .mount(&missing, "/missing", false);
+
+    let sandbox = SandboxImplementation::run(config.build()).unwrap();
+    let error = format!("{:#}", sandbox.wait().unwrap_err());
+    assert!(
+        error.contains("Failed to create sandbox filesystem"),
+        "{}",
+        error
+    );
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap();
+    assert!(!mountinfo.contains("/tabox-"), "{}", mountinfo);
+}
+
+#[test]
+fn test_no_chroot_escape() {
+    // A file on the host, hidden in the sandbox by the tmpfs mounted at /tmp
+    let host = tempfile::TempDir::new().unwrap();
+    let marker = host.path().join("marker");
+    std::fs::write(&marker, "").unwrap();
+
+    let program = r#"
+       #include <fcntl.h>
+       #include <unistd.h>
+       int main() {
+           const char *marker = "MARKER";
+           int fd = open("/", O_RDONLY | O_DIRECTORY);
+           if (fd < 0) return 1;
+           // With a plain chroot, a new one leaves the fd of the old root outside of it
+           chroot("/tmp");
+           for (int i = 0; i < 64; i++) {
+               int parent = openat(fd, "..", O_RDONLY | O_DIRECTORY);
+               if (parent < 0) return 2;
+               close(fd);
+               fd = parent;
+           }
+           if (faccessat(fd, marker + 1, F_OK, 0) == 0) return 3;
+           if (access(marker, F_OK) == 0) return 4;
+           return 0;
+       }
+    "#
+    .replace("MARKER", marker.to_str().unwrap());
+
+    let mut config = SandboxConfiguration::default();
+    let result = exec(&program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+}
+
+#[test]
+fn test_proc_masked() {
+    let program = r#"
+       #include <fcntl.h>
+       #include <unistd.h>
+       int main() {
+           char buf[16];
+           int fd = open("/proc/kcore", O_RDONLY);
+           if (fd >= 0 && read(fd, buf, sizeof(buf)) > 0) return 1;
+           if (open("/proc/sys/kernel/hostname", O_WRONLY) >= 0) return 2;
+           fd = open("/proc/keys", O_RDONLY);
+           if (fd >= 0 && read(fd, buf, sizeof(buf)) != 0) return 3;
+           fd = open("/proc/sysrq-trigger", O_WRONLY);
+           if (fd >= 0 && write(fd, "h", 1) >= 0) return 5;
+           fd = open("/proc/self/status", O_RDONLY);
+           if (fd < 0 || read(fd, buf, sizeof(buf)) <= 0) return 4;
+           return 0;
+       }
+    "#;
+
+    let mut config = SandboxConfiguration::default();
+    config.mount_proc(true);
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+}
+
+#[test]
+fn test_proc_only_pids() {
+    use crate::configuration::ProcOptions;
+
+    let program = r#"
+       #include <unistd.h>
+       int main() {
+           if (access("/proc/cpuinfo", F_OK) == 0) return 1;
+           if (access("/proc/self/status", R_OK) != 0) return 2;
+           return 0;
+       }
+    "#;
+
+    let mut config = SandboxConfiguration::default();
+    config.mount_proc(true).proc_options(ProcOptions {
+        hide_pids: true,
+        only_pids: true,
+        ..Default::default()
+    });
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+}
+
+#[test]
+fn test_no_host_sysv_ipc() {
+    let program = r#"
+       #include <sys/ipc.h>
+       #include <sys/msg.h>
+       #include <sys/shm.h>
+       int main() {
+           if (shmget(KEY, 0, 0) >= 0) return 1;
+           if (msgget(KEY, 0) >= 0) return 2;
+           return 0;
+       }
+    "#;
+
+    let key = 0x7ab0_0000 | (std::process::id() as libc::key_t & 0xffff);
+    let shm = unsafe { libc::shmget(key, 4096, libc::IPC_CREAT | 0o666) };
+    let msg = unsafe { libc::msgget(key, libc::IPC_CREAT | 0o666) };
+    assert!(shm >= 0 && msg >= 0);
+
+    let mut config = SandboxConfiguration::default();
+    let result = exec(&program.replace("KEY", &key.to_string()), &mut config, "");
+    unsafe {
+        libc::shmctl(shm, libc::IPC_RMID, std::ptr::null_mut());
+        libc::msgctl(msg, libc::IPC_RMID, std::ptr::null_mut());
+    }
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+}
+
+#[test]
+fn test_no_host_abstract_sockets() {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixListener};
+
+    let program = r#"
+       #include <string.h>
+       #include <sys/socket.h>
+       #include <sys/un.h>
+       int main() {
+           int fd = socket(AF_UNIX, SOCK_STREAM, 0);
+           struct sockaddr_un addr = {0};
+           addr.sun_family = AF_UNIX;
+           strcpy(addr.sun_path + 1, "NAME");
+           socklen_t len = sizeof(sa_family_t) + 1 + strlen("NAME");
+           return connect(fd, (struct sockaddr*)&addr, len) == 0;
+       }
+    "#;
+
+    let name = format!("tabox-test-{}", std::process::id());
+    let address = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+    let _listener = UnixListener::bind_addr(&address).unwrap();
+
+    let mut config = SandboxConfiguration::default();
+    let result = exec(&program.replace("NAME", &name), &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+}
+
+#[test]
+fn test_no_host_cgroup_paths() {
+    let program = r#"
+       #include <stdio.h>
+       int main() {
+           FILE *f = fopen("/proc/self/cgroup", "r");
+           if (!f) return 1;
+           char line[1024];
+           while (fgets(line, sizeof(line), f)) printf("%s", line);
+           return 0;
+       }
+    "#;
+
+    let mut config = SandboxConfiguration::default();
+    config.mount_proc(true);
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert!(!result.stdout.is_empty());
+    for line in result.stdout.lines() {
+        assert!(line.ends_with(":/"), "{}", result.stdout);
+    }
+}
+
+#[test]
+fn test_kill_threaded_process() {
+    let program = r#"
+       #include <pthread.h>
+       #include <sys/stat.h>
+       #include <unistd.h>
+       void *violate(void *arg) { chmod("/tmp", 0777); return NULL; }
+       int main() {
+           pthread_t thread;
+           pthread_create(&thread, NULL, violate, NULL);
+           pthread_join(thread, NULL);
+           return 0;
+       }
+    "#;
+
+    let mut config = SandboxConfiguration::default();
+    config.syscall_filter(SyscallFilter::build(true, false));
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::Signal(libc::SIGSYS));
+}
+
+#[test]
+fn test_seccomp_actions() {
+    let program = r#"
+       #include <errno.h>
+       #include <signal.h>
+       #include <stdio.h>
+       #include <sys/syscall.h>
+       #include <unistd.h>
+       void handler(int sig) { printf("trap "); }
+       int main() {
+           signal(SIGSYS, handler);
+           syscall(SYS_getppid);
+           if (syscall(SYS_getuid) < 0) return 1;
+           if (syscall(SYS_getgid) != -1 || errno != ENOSYS) return 2;
+           return 0;
+       }
+    "#;
+
+    let mut filter = SyscallFilter::default();
+    filter
+        .default_action(SyscallFilterAction::Allow)
+        .add_rule("getppid", SyscallFilterAction::Trap)
+        .add_rule("getuid", SyscallFilterAction::Log)
+        .add_rule("getgid", SyscallFilterAction::Trace(42));
+
+    let mut config = SandboxConfiguration::default();
+    config.syscall_filter(filter);
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+    assert_eq!(result.stdout, "trap ");
+}
+
+#[test]
+fn test_seccomp_argument_conditions() {
+    use crate::syscall_filter::{ArgumentCondition, ComparisonOperator};
+
+    let program = r#"
+       #include <errno.h>
+       #include <pthread.h>
+       #include <sys/socket.h>
+       #include <unistd.h>
+       void *thread(void *arg) { return NULL; }
+       int main() {
+           if (socket(AF_UNIX, SOCK_STREAM, 0) < 0) return 1;
+           if (socket(AF_INET, SOCK_STREAM, 0) >= 0 || errno != EACCES) return 2;
+           pthread_t t;
+           if (pthread_create(&t, NULL, thread, NULL)) return 3;
+           pthread_join(t, NULL);
+           if (fork() >= 0 || errno != EPERM) return 4;
+           return 0;
+       }
+    "#;
+
+    let mut filter = SyscallFilter::default();
+    filter
+        .default_action(SyscallFilterAction::Allow)
+        .add_conditional_rule(
+            "socket",
+            SyscallFilterAction::Errno(libc::EACCES as u32),
+            vec![ArgumentCondition::new(
+                0,
+                ComparisonOperator::NotEqual,
+                libc::AF_UNIX as u64,
+            )],
+        )
+        // glibc falls back to clone when clone3 is not available
+        .add_rule("clone3", SyscallFilterAction::Errno(libc::ENOSYS as u32))
+        // New processes are created by clone without CLONE_THREAD
+        .add_conditional_rule(
+            "clone",
+            SyscallFilterAction::Errno(libc::EPERM as u32),
+            vec![ArgumentCondition::new(
+                0,
+                ComparisonOperator::MaskedEqual(libc::CLONE_THREAD as u64),
+                0,
+            )],
+        );
+
+    let mut config = SandboxConfiguration::default();
+    config.syscall_filter(filter);
+
+    let result = exec(program, &mut config, "");
+
+    assert_eq!(result.result.status, ExitStatus::ExitCode(0));
+}
@@ -163,3 +163,42 @@ pub fn strsignal(signal: i32) -> Option<String> {
         None
     }
 }
+
+/// Check if the name matches a pattern with `*` (any sequence) and `?` (any character)
+pub fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` in the pattern and of the name when it was found
+    let mut backtrack = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wildcard_match;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match(b"*.txt", b"output.txt"));
+        assert!(wildcard_match(b"out?ut*", b"output.txt"));
+        assert!(wildcard_match(b"*", b""));
+        assert!(wildcard_match(b"a*b*c", b"aXbYbZc"));
+        assert!(!wildcard_match(b"*.txt", b"output.txt.bak"));
+        assert!(!wildcard_match(b"?", b""));
+        assert!(!wildcard_match(b"a*b", b"aXc"));
+    }
+}